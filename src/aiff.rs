@@ -0,0 +1,244 @@
+use crate::store::Snapshot;
+use crate::{archive, content::Item};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+
+// OP-Z drum packs are AIFF files whose APPL chunk carries Teenage Engineering's settings
+// as JSON, after an "op-1" signature: the pack name, and per key (24 of them) start/end
+// points, pitch, volume, playmode and reverse.
+const SIGNATURE: &[u8] = b"op-1";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Pack {
+    pub name: String,
+    /// Keys with their own sample region
+    pub slices: usize,
+    /// Length of the audio, from the COMM chunk
+    pub seconds: f64,
+    #[serde(skip)]
+    pub settings: Map<String, Value>,
+}
+
+// Metadata already read, per content hash: backups share most of their packs
+static PACKS: Mutex<BTreeMap<String, Option<Pack>>> = Mutex::new(BTreeMap::new());
+
+fn be32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+// 80-bit IEEE extended, the way AIFF stores the sample rate
+fn extended(b: &[u8]) -> f64 {
+    let exponent = (((b[0] & 0x7f) as i32) << 8) | b[1] as i32;
+    let mantissa = u64::from_be_bytes(b[2..10].try_into().unwrap());
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
+
+// Keys whose end lies past their start, counting repeats of the same region once
+fn count_slices(settings: &Map<String, Value>) -> usize {
+    let points = |key: &str| settings.get(key).and_then(Value::as_array).cloned().unwrap_or_default();
+    let mut regions: Vec<(i64, i64)> = points("start").iter().zip(points("end").iter())
+        .filter_map(|(s, e)| Some((s.as_i64()?, e.as_i64()?)))
+        .filter(|(s, e)| e > s)
+        .collect();
+    regions.sort();
+    regions.dedup();
+    regions.len()
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Pack, String> {
+    if bytes.len() < 12 || &bytes[..4] != b"FORM" || !matches!(&bytes[8..12], b"AIFF" | b"AIFC") {
+        return Err("not an AIFF file".to_string());
+    }
+    let (mut settings, mut seconds) = (None, 0.0);
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let (id, len) = (&rest[..4], be32(&rest[4..8]) as usize);
+        let data = rest.get(8..8 + len).ok_or("truncated AIFF chunk")?;
+        match id {
+            b"COMM" if len >= 18 => {
+                let rate = extended(&data[8..18]);
+                if rate > 0.0 {
+                    seconds = be32(&data[2..6]) as f64 / rate;
+                }
+            }
+            b"APPL" if data.starts_with(SIGNATURE) => {
+                let json = String::from_utf8_lossy(&data[SIGNATURE.len()..]);
+                let json = json.trim_end_matches(|c: char| c == '\0' || c.is_whitespace());
+                settings = Some(serde_json::from_str::<Map<String, Value>>(json).map_err(|e| format!("bad pack settings: {}", e))?);
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        rest = rest.get(8 + len + len % 2..).unwrap_or_default();
+    }
+    let settings = settings.ok_or("no OP-Z metadata in AIFF")?;
+    Ok(Pack {
+        name: settings.get("name").and_then(Value::as_str).unwrap_or_default().to_string(),
+        slices: count_slices(&settings),
+        seconds,
+        settings,
+    })
+}
+
+pub fn read(r: &mut dyn Read) -> Result<Pack, String> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    parse(&bytes)
+}
+
+pub fn is_pack(rel: &str) -> bool {
+    matches!(Item::classify(rel), Item::SamplePack { .. })
+        && Path::new(rel).extension().is_some_and(|e| e.eq_ignore_ascii_case("aif") || e.eq_ignore_ascii_case("aiff"))
+}
+
+fn cached(hash: Option<&str>, load: impl FnOnce() -> Option<Pack>) -> Option<Pack> {
+    let Some(hash) = hash else { return load() };
+    if let Some(pack) = PACKS.lock().unwrap().get(hash) {
+        return pack.clone();
+    }
+    let pack = load();
+    PACKS.lock().unwrap().insert(hash.to_string(), pack.clone());
+    pack
+}
+
+// The metadata of one file in a backup; None when it isn't a readable drum pack
+pub fn pack_in(root: &Path, snap: &Snapshot, rel: &str) -> Option<Pack> {
+    let hash = snap.manifest.files.get(rel)?.hash.as_deref();
+    if snap.archive.is_some() {
+        return cached(hash, || packs(root, snap).remove(rel));
+    }
+    cached(hash, || read(&mut snap.open(root, rel).ok()?).ok())
+}
+
+// Every drum pack in a backup, by path. An archive is read in one pass.
+pub fn packs(root: &Path, snap: &Snapshot) -> BTreeMap<String, Pack> {
+    let wanted: Vec<&String> = snap.manifest.files.keys().filter(|rel| is_pack(rel)).collect();
+    let Some(path) = &snap.archive else {
+        return wanted.into_iter().filter_map(|rel| Some((rel.clone(), pack_in(root, snap, rel)?))).collect();
+    };
+    let mut found = BTreeMap::new();
+    archive::each_entry(path, |rel, r| {
+        if is_pack(rel) && let Ok(pack) = read(r) {
+            found.insert(rel.to_string(), pack);
+        }
+        Ok(())
+    }).ok();
+    let mut cache = PACKS.lock().unwrap();
+    for rel in wanted {
+        if let Some(hash) = snap.manifest.files[rel].hash.as_deref() {
+            cache.insert(hash.to_string(), found.get(rel).cloned());
+        }
+    }
+    found
+}
+
+// What differs between two versions of a pack: "name 808 → punchy", then the changed
+// setting names ("pitch", "volume", ...)
+pub fn changed_settings(old: &Pack, new: &Pack) -> Vec<String> {
+    let mut changed = Vec::new();
+    if old.name != new.name {
+        changed.push(format!("name {} → {}", old.name, new.name));
+    }
+    let keys: std::collections::BTreeSet<&String> = old.settings.keys().chain(new.settings.keys()).collect();
+    changed.extend(keys.into_iter()
+        .filter(|k| *k != "name" && old.settings.get(*k) != new.settings.get(*k))
+        .cloned());
+    changed
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // A minimal mono 44.1 kHz AIFF with an OP-Z APPL chunk; `frames` sets the length
+    pub fn aiff(json: &str, frames: u32) -> Vec<u8> {
+        let chunk = |id: &[u8], data: &[u8]| {
+            let mut c = [id, &(data.len() as u32).to_be_bytes(), data].concat();
+            if data.len() % 2 == 1 {
+                c.push(0);
+            }
+            c
+        };
+        // 44100 as an 80-bit extended float
+        let rate = [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0];
+        let comm = [&1u16.to_be_bytes()[..], &frames.to_be_bytes(), &16u16.to_be_bytes(), &rate].concat();
+        let body = [
+            b"AIFF".to_vec(),
+            chunk(b"COMM", &comm),
+            chunk(b"APPL", &[SIGNATURE, json.as_bytes()].concat()),
+            chunk(b"SSND", &[0u8; 12]),
+        ].concat();
+        [b"FORM".to_vec(), (body.len() as u32).to_be_bytes().to_vec(), body].concat()
+    }
+
+    pub const KIT: &str = r#"{"drum_version":2,"type":"drum","name":"808 kit","octave":0,
+        "start":[0,100,200,200],"end":[100,200,300,300],"pitch":[0,0,0,0],"volume":[8192,8192,8192,8192]}"#;
+
+    // ── parse ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn parse_reads_name_slices_and_length() {
+        let pack = parse(&aiff(KIT, 88200)).unwrap();
+        assert_eq!(pack.name, "808 kit");
+        assert_eq!(pack.slices, 3);
+        assert!((pack.seconds - 2.0).abs() < 1e-9);
+        assert_eq!(pack.settings["type"], "drum");
+    }
+
+    #[test]
+    fn parse_tolerates_odd_chunks_and_nul_padding() {
+        let json = format!("{}\0\0", r#"{"name":"odd"}"#);
+        assert_eq!(parse(&aiff(&json, 1)).unwrap().name, "odd");
+    }
+
+    #[test]
+    fn parse_rejects_plain_and_foreign_files() {
+        assert!(parse(b"RIFF....WAVE").is_err());
+        let plain = aiff("{}", 1);
+        assert!(parse(&plain).is_ok());
+        let mut no_meta = plain.clone();
+        let at = no_meta.windows(4).position(|w| w == SIGNATURE).unwrap();
+        no_meta[at..at + 4].copy_from_slice(b"xxxx");
+        assert!(parse(&no_meta).unwrap_err().contains("no OP-Z metadata"));
+        assert!(parse(&plain[..plain.len() - 5]).is_err());
+    }
+
+    // ── changed_settings ────────────────────────────────────────────────────
+
+    #[test]
+    fn changed_settings_names_what_differs() {
+        let old = parse(&aiff(KIT, 10)).unwrap();
+        let new = parse(&aiff(&KIT.replace("808 kit", "punchy").replace("\"pitch\":[0,0", "\"pitch\":[5,0"), 10)).unwrap();
+        assert_eq!(changed_settings(&old, &new), vec!["name 808 kit → punchy", "pitch"]);
+        assert!(changed_settings(&old, &old).is_empty());
+    }
+
+    // ── packs ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn packs_reads_stored_and_archived_backups() {
+        let root = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(src.path().join("samplepacks/1-kick/01")).unwrap();
+        std::fs::write(src.path().join("samplepacks/1-kick/01/808.aif"), aiff(KIT, 10)).unwrap();
+        std::fs::create_dir_all(src.path().join("projects")).unwrap();
+        std::fs::write(src.path().join("projects/project01.opz"), b"bars").unwrap();
+
+        crate::store::store_snapshot(root.path(), src.path(), "2026-03-20_10-00-00").unwrap();
+        archive::snapshot(root.path(), src.path(), "2026-03-21_10-00-00", archive::Kind::Zip).unwrap();
+        for name in ["2026-03-20_10-00-00", "2026-03-21_10-00-00"] {
+            let snap = Snapshot::load(root.path(), name).unwrap();
+            let packs = packs(root.path(), &snap);
+            assert_eq!(packs.keys().collect::<Vec<_>>(), vec!["samplepacks/1-kick/01/808.aif"], "{}", name);
+            assert_eq!(packs["samplepacks/1-kick/01/808.aif"].name, "808 kit");
+            assert_eq!(pack_in(root.path(), &snap, "projects/project01.opz"), None);
+        }
+    }
+}
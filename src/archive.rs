@@ -0,0 +1,440 @@
+use crate::manifest::{MANIFEST, Manifest};
+use crate::store::{Meter, Report, hex};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// A snapshot packed into one file: manifest.json first, then every file's bytes
+// under files/<rel>. Lives next to the snapshot folders as <root>/<name>.tar.zst or .zip.
+const FILES: &str = "files/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    /// Zstandard-compressed tarball (.tar.zst)
+    Zstd,
+    /// Deflate zip (.zip), opens anywhere
+    Zip,
+}
+
+impl Kind {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Kind::Zstd => ".tar.zst",
+            Kind::Zip => ".zip",
+        }
+    }
+
+    pub fn of(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_str()?;
+        if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Kind::Zstd)
+        } else if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+// Snapshot name for an archive file in the backup root
+pub fn name_of(file_name: &str) -> Option<&str> {
+    [Kind::Zstd, Kind::Zip].iter().find_map(|k| file_name.strip_suffix(k.extension()))
+}
+
+pub fn find(root: &Path, name: &str) -> Option<PathBuf> {
+    [Kind::Zstd, Kind::Zip].iter()
+        .map(|k| root.join(format!("{}{}", name, k.extension())))
+        .find(|p| p.is_file())
+}
+
+fn err(path: &Path) -> impl Fn(&dyn std::fmt::Display) -> String + '_ {
+    move |e| format!("{}: {}", path.display(), e)
+}
+
+// Packs the manifest and the content of every file it lists (read from `content(rel)`)
+// into dest. Written to a temp name first, so a half-written archive never looks like a
+// snapshot. Returns the archive's size.
+pub fn write<'a>(dest: &Path, kind: Kind, manifest: &Manifest, content: impl Fn(&str) -> Result<Box<dyn Read + 'a>, String>) -> Result<u64, String> {
+    let tmp = dest.with_file_name(format!(".tmp-{}", std::process::id()));
+    let e = err(dest);
+    let json = serde_json::to_vec_pretty(manifest).map_err(|x| e(&x))?;
+    let out = BufWriter::new(File::create(&tmp).map_err(|x| e(&x))?);
+    let mut meter = Meter::new(manifest.total_size())?;
+
+    match kind {
+        Kind::Zstd => {
+            let mut tar = tar::Builder::new(zstd::Encoder::new(out, 3).map_err(|x| e(&x))?);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(json.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, MANIFEST, json.as_slice()).map_err(|x| e(&x))?;
+            for (rel, entry) in &manifest.files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(entry.size);
+                header.set_mode(0o644);
+                header.set_mtime(entry.mtime.max(0) as u64);
+                tar.append_data(&mut header, format!("{}{}", FILES, rel), content(rel)?).map_err(|x| e(&x))?;
+                meter.advance(entry.size, rel);
+            }
+            tar.into_inner().and_then(|z| z.finish()).and_then(|mut w| w.flush()).map_err(|x| e(&x))?;
+        }
+        Kind::Zip => {
+            let mut zip = zip::ZipWriter::new(out);
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .large_file(true);
+            zip.start_file(MANIFEST, options).map_err(|x| e(&x))?;
+            zip.write_all(&json).map_err(|x| e(&x))?;
+            for (rel, entry) in &manifest.files {
+                zip.start_file(format!("{}{}", FILES, rel), options).map_err(|x| e(&x))?;
+                std::io::copy(&mut content(rel)?, &mut zip).map_err(|x| e(&x))?;
+                meter.advance(entry.size, rel);
+            }
+            zip.finish().and_then(|mut w| w.flush().map_err(Into::into)).map_err(|x| e(&x))?;
+        }
+    }
+    meter.bar.finish();
+
+    std::fs::rename(&tmp, dest).map_err(|x| e(&x))?;
+    Ok(std::fs::metadata(dest).map_err(|x| e(&x))?.len())
+}
+
+// Backs up src straight into <root>/<name><ext>, bypassing the object store. The manifest
+// leads the archive, so every file is read once into memory and hashed; those same bytes
+// are packed, and a file changing meanwhile can't make them disagree. Returns the
+// archive's size.
+pub fn snapshot(root: &Path, src: &Path, name: &str, kind: Kind) -> Result<u64, String> {
+    let mut manifest = crate::store::scan(src);
+    let mut contents = BTreeMap::new();
+    for (rel, entry) in manifest.files.iter_mut() {
+        let path = src.join(rel);
+        let data = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        entry.size = data.len() as u64;
+        entry.hash = Some(hex(&Sha256::digest(&data)));
+        contents.insert(rel.clone(), data);
+    }
+    std::fs::create_dir_all(root).map_err(|e| e.to_string())?;
+    write(&root.join(format!("{}{}", name, kind.extension())), kind, &manifest, |rel| {
+        let data = contents.get(rel).ok_or_else(|| format!("{}: not read", rel))?;
+        Ok(Box::new(data.as_slice()) as Box<dyn Read>)
+    })
+}
+
+// Calls `f` with the relative path and a reader for every file in the archive, in
+// archive order. The manifest is handed over as MANIFEST.
+pub fn each_entry(path: &Path, mut f: impl FnMut(&str, &mut dyn Read) -> Result<(), String>) -> Result<(), String> {
+    let e = err(path);
+    let file = BufReader::new(File::open(path).map_err(|x| e(&x))?);
+    match Kind::of(path).ok_or_else(|| format!("{}: not a .tar.zst or .zip", path.display()))? {
+        Kind::Zstd => {
+            let mut tar = tar::Archive::new(zstd::Decoder::new(file).map_err(|x| e(&x))?);
+            for entry in tar.entries().map_err(|x| e(&x))? {
+                let mut entry = entry.map_err(|x| e(&x))?;
+                let name = entry.path().map_err(|x| e(&x))?.to_string_lossy().into_owned();
+                f(name.strip_prefix(FILES).unwrap_or(&name), &mut entry)?;
+            }
+        }
+        Kind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(|x| e(&x))?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(|x| e(&x))?;
+                let name = entry.name().to_string();
+                f(name.strip_prefix(FILES).unwrap_or(&name), &mut entry)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The bytes of every file `pick` accepts, collected in one pass over the archive
+pub fn read_files(path: &Path, pick: impl Fn(&str) -> bool) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    let mut found = BTreeMap::new();
+    each_entry(path, |rel, r| {
+        if rel != MANIFEST && pick(rel) && !found.contains_key(rel) {
+            let mut bytes = Vec::new();
+            r.read_to_end(&mut bytes).map_err(|x| err(path)(&x))?;
+            found.insert(rel.to_string(), bytes);
+        }
+        Ok(())
+    })?;
+    Ok(found)
+}
+
+// One file's bytes; reading several, use read_files
+pub fn read_file(path: &Path, rel: &str) -> Result<Vec<u8>, String> {
+    read_files(path, |name| name == rel)?.remove(rel)
+        .ok_or_else(|| format!("{}: no {}", path.display(), rel))
+}
+
+// Only the first entry is read, so listing archived snapshots stays cheap
+pub fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    let e = err(path);
+    let file = BufReader::new(File::open(path).map_err(|x| e(&x))?);
+    let json = match Kind::of(path).ok_or_else(|| format!("{}: not a .tar.zst or .zip", path.display()))? {
+        Kind::Zstd => {
+            let mut tar = tar::Archive::new(zstd::Decoder::new(file).map_err(|x| e(&x))?);
+            let mut first = tar.entries().map_err(|x| e(&x))?
+                .next()
+                .ok_or_else(|| format!("{}: empty archive", path.display()))?
+                .map_err(|x| e(&x))?;
+            if first.path().map_err(|x| e(&x))?.as_os_str() != MANIFEST {
+                return Err(format!("{}: no {}", path.display(), MANIFEST));
+            }
+            let mut json = String::new();
+            first.read_to_string(&mut json).map_err(|x| e(&x))?;
+            json
+        }
+        Kind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(|x| e(&x))?;
+            let mut json = String::new();
+            zip.by_name(MANIFEST).map_err(|x| e(&x))?.read_to_string(&mut json).map_err(|x| e(&x))?;
+            json
+        }
+    };
+    Manifest::parse(json.as_bytes()).map_err(|x| e(&x))
+}
+
+// Writes the files `manifest` lists straight out of the archive into dst, with their
+// recorded mtimes. Returns bytes written.
+pub fn extract(path: &Path, manifest: &Manifest, dst: &Path) -> Result<u64, String> {
+    let mut meter = Meter::new(manifest.total_size())?;
+    let mut bytes = 0u64;
+    each_entry(path, |rel, reader| {
+        let Some(entry) = manifest.files.get(rel) else { return Ok(()) };
+        let out = dst.join(rel);
+        let e = err(&out);
+        if let Some(parent) = out.parent() {
+            std::fs::create_dir_all(parent).map_err(|x| e(&x))?;
+        }
+        let mut file = File::create(&out).map_err(|x| e(&x))?;
+        bytes += std::io::copy(reader, &mut file).map_err(|x| e(&x))?;
+        if entry.mtime > 0 {
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(entry.mtime as u64)).map_err(|x| e(&x))?;
+        }
+        meter.advance(entry.size, rel);
+        Ok(())
+    })?;
+    meter.bar.finish();
+    Ok(bytes)
+}
+
+// Re-hashes every file in the archive against the manifest it carries
+pub fn verify(path: &Path, manifest: &Manifest) -> Result<Report, String> {
+    let mut report = Report::default();
+    let mut seen = Vec::new();
+    let mut meter = Meter::new(manifest.total_size())?;
+    each_entry(path, |rel, reader| {
+        if rel == MANIFEST {
+            return Ok(());
+        }
+        let Some(entry) = manifest.files.get(rel) else {
+            report.extra.push(rel.to_string());
+            return Ok(());
+        };
+        let mut hasher = Sha256::new();
+        std::io::copy(reader, &mut hasher).map_err(|x| err(path)(&x))?;
+        if entry.hash.as_deref().is_some_and(|h| h != hex(&hasher.finalize())) {
+            report.corrupted.push(rel.to_string());
+        }
+        seen.push(rel.to_string());
+        meter.advance(entry.size, rel);
+        Ok(())
+    })?;
+    meter.bar.finish();
+    report.missing = manifest.files.keys().filter(|rel| !seen.contains(rel)).cloned().collect();
+    Ok(report)
+}
+
+// Swaps the embedded manifest (e.g. to mark the snapshot failed), copying every other
+// entry across unchanged
+pub fn replace_manifest(path: &Path, manifest: &Manifest) -> Result<(), String> {
+    let e = err(path);
+    let json = serde_json::to_vec_pretty(manifest).map_err(|x| e(&x))?;
+    let tmp = path.with_file_name(format!(".tmp-{}", std::process::id()));
+    let out = BufWriter::new(File::create(&tmp).map_err(|x| e(&x))?);
+
+    match Kind::of(path).ok_or_else(|| format!("{}: not a .tar.zst or .zip", path.display()))? {
+        Kind::Zstd => {
+            let mut tar = tar::Builder::new(zstd::Encoder::new(out, 3).map_err(|x| e(&x))?);
+            let input = BufReader::new(File::open(path).map_err(|x| e(&x))?);
+            let mut old = tar::Archive::new(zstd::Decoder::new(input).map_err(|x| e(&x))?);
+            for entry in old.entries().map_err(|x| e(&x))? {
+                let entry = entry.map_err(|x| e(&x))?;
+                let mut header = entry.header().clone();
+                let name = entry.path().map_err(|x| e(&x))?.into_owned();
+                if name.as_os_str() == MANIFEST {
+                    header.set_size(json.len() as u64);
+                    tar.append_data(&mut header, &name, json.as_slice()).map_err(|x| e(&x))?;
+                } else {
+                    tar.append_data(&mut header, &name, entry).map_err(|x| e(&x))?;
+                }
+            }
+            tar.into_inner().and_then(|z| z.finish()).and_then(|mut w| w.flush()).map_err(|x| e(&x))?;
+        }
+        Kind::Zip => {
+            let mut old = zip::ZipArchive::new(BufReader::new(File::open(path).map_err(|x| e(&x))?)).map_err(|x| e(&x))?;
+            let mut zip = zip::ZipWriter::new(out);
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+            for i in 0..old.len() {
+                let entry = old.by_index_raw(i).map_err(|x| e(&x))?;
+                if entry.name() == MANIFEST {
+                    drop(entry);
+                    zip.start_file(MANIFEST, options).map_err(|x| e(&x))?;
+                    zip.write_all(&json).map_err(|x| e(&x))?;
+                } else {
+                    zip.raw_copy_file(entry).map_err(|x| e(&x))?;
+                }
+            }
+            zip.finish().and_then(|mut w| w.flush().map_err(Into::into)).map_err(|x| e(&x))?;
+        }
+    }
+    std::fs::rename(&tmp, path).map_err(|x| e(&x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileEntry;
+    use std::fs;
+
+    // Two files on disk and a manifest (with hashes) describing them
+    fn source() -> (tempfile::TempDir, Manifest) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("projects")).unwrap();
+        fs::write(dir.path().join("projects/project01.opz"), b"pattern data").unwrap();
+        fs::write(dir.path().join("bounce.aif"), b"bounce").unwrap();
+        let mut m = Manifest::new("/Volumes/OP-Z");
+        for (rel, size) in [("projects/project01.opz", 12), ("bounce.aif", 6)] {
+            let hash = crate::store::hash_file(&dir.path().join(rel)).unwrap();
+            m.files.insert(rel.into(), FileEntry { size, mtime: 1_700_000_000, hash: Some(hash) });
+        }
+        (dir, m)
+    }
+
+    fn pack(kind: Kind) -> (tempfile::TempDir, PathBuf, Manifest) {
+        let (src, m) = source();
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join(format!("2026-03-20_10-00-00{}", kind.extension()));
+        write(&path, kind, &m, |rel| Ok(Box::new(File::open(src.path().join(rel)).unwrap()))).unwrap();
+        (out, path, m)
+    }
+
+    // ── Kind / name_of / find ───────────────────────────────────────────────
+
+    #[test]
+    fn kind_from_extension() {
+        assert_eq!(Kind::of(Path::new("a/b.tar.zst")), Some(Kind::Zstd));
+        assert_eq!(Kind::of(Path::new("b.tzst")), Some(Kind::Zstd));
+        assert_eq!(Kind::of(Path::new("b.zip")), Some(Kind::Zip));
+        assert_eq!(Kind::of(Path::new("b.tar")), None);
+    }
+
+    #[test]
+    fn name_of_strips_archive_extension() {
+        assert_eq!(name_of("2026-03-20_10-00-00.tar.zst"), Some("2026-03-20_10-00-00"));
+        assert_eq!(name_of("2026-03-20_10-00-00.zip"), Some("2026-03-20_10-00-00"));
+        assert_eq!(name_of("notes.txt"), None);
+    }
+
+    #[test]
+    fn find_locates_either_kind() {
+        let (out, path, _) = pack(Kind::Zip);
+        assert_eq!(find(out.path(), "2026-03-20_10-00-00"), Some(path));
+        assert_eq!(find(out.path(), "2026-03-21_10-00-00"), None);
+    }
+
+    // ── snapshot / write / read_manifest / extract ──────────────────────────
+
+    #[test]
+    fn snapshot_hashes_and_packs_device() {
+        let (src, m) = source();
+        let root = tempfile::tempdir().unwrap();
+        snapshot(root.path(), src.path(), "s", Kind::Zstd).unwrap();
+        let packed = read_manifest(&root.path().join("s.tar.zst")).unwrap();
+        assert_eq!(packed.files["bounce.aif"].hash, m.files["bounce.aif"].hash);
+        assert_eq!(packed.files.len(), 2);
+        assert!(verify(&root.path().join("s.tar.zst"), &packed).unwrap().is_clean());
+    }
+
+    #[test]
+    fn roundtrip_both_kinds() {
+        for kind in [Kind::Zstd, Kind::Zip] {
+            let (_out, path, m) = pack(kind);
+            assert_eq!(read_manifest(&path).unwrap(), m);
+
+            let dst = tempfile::tempdir().unwrap();
+            assert_eq!(extract(&path, &m, dst.path()).unwrap(), 18);
+            assert_eq!(fs::read(dst.path().join("projects/project01.opz")).unwrap(), b"pattern data");
+            let meta = fs::metadata(dst.path().join("bounce.aif")).unwrap();
+            assert_eq!(crate::manifest::mtime(&meta), 1_700_000_000);
+        }
+    }
+
+    #[test]
+    fn extract_only_writes_listed_files() {
+        let (_out, path, mut m) = pack(Kind::Zstd);
+        m.files.remove("bounce.aif");
+        let dst = tempfile::tempdir().unwrap();
+        extract(&path, &m, dst.path()).unwrap();
+        assert!(dst.path().join("projects/project01.opz").is_file());
+        assert!(!dst.path().join("bounce.aif").exists());
+    }
+
+    #[test]
+    fn write_leaves_no_temp_file() {
+        let (out, _, _) = pack(Kind::Zstd);
+        let names: Vec<_> = fs::read_dir(out.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, vec!["2026-03-20_10-00-00.tar.zst"]);
+    }
+
+    #[test]
+    fn read_files_collects_picked_files_in_one_pass() {
+        for kind in [Kind::Zstd, Kind::Zip] {
+            let (_out, path, _) = pack(kind);
+            let files = read_files(&path, |rel| rel.starts_with("projects/") || rel == MANIFEST).unwrap();
+            assert_eq!(files.keys().collect::<Vec<_>>(), vec!["projects/project01.opz"]);
+            assert_eq!(files["projects/project01.opz"], b"pattern data");
+            assert_eq!(read_file(&path, "bounce.aif").unwrap().len(), 6);
+            assert!(read_file(&path, "nope").is_err());
+        }
+    }
+
+    #[test]
+    fn read_manifest_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("x.tar"), b"junk").unwrap();
+        assert!(read_manifest(&dir.path().join("x.tar")).is_err());
+    }
+
+    // ── verify / replace_manifest ───────────────────────────────────────────
+
+    #[test]
+    fn verify_flags_hash_mismatch() {
+        for kind in [Kind::Zstd, Kind::Zip] {
+            let (_out, path, mut m) = pack(kind);
+            assert!(verify(&path, &m).unwrap().is_clean());
+
+            m.files.get_mut("bounce.aif").unwrap().hash = Some("00".repeat(32));
+            m.files.insert("gone.aif".into(), FileEntry { size: 1, mtime: 0, hash: None });
+            let report = verify(&path, &m).unwrap();
+            assert_eq!(report.corrupted, vec!["bounce.aif"]);
+            assert_eq!(report.missing, vec!["gone.aif"]);
+        }
+    }
+
+    #[test]
+    fn replace_manifest_keeps_content() {
+        for kind in [Kind::Zstd, Kind::Zip] {
+            let (_out, path, mut m) = pack(kind);
+            m.failed = true;
+            replace_manifest(&path, &m).unwrap();
+            assert!(read_manifest(&path).unwrap().failed);
+            assert!(verify(&path, &m).unwrap().is_clean());
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use crate::manifest::{self, MANIFEST};
+use crate::store::{self, OBJECTS, SEALED_MANIFEST, Snapshot, object_key};
+use crate::{archive, crypto};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// Where backups are kept: the local root itself (Local), and remotes pushed to and pulled
+// from. Every one holds the same layout (<name>/manifest.json, <name>.tar.zst,
+// .objects/ab/<hash>), addressed by '/'-separated keys. Bytes go across as stored, so
+// encrypted roots stay encrypted.
+pub trait Backend {
+    /// Shown in messages, e.g. sftp://studio/srv/opz
+    fn describe(&self) -> String;
+    /// Files and folders directly inside `dir` ("" for the top); empty if it doesn't exist
+    fn list(&self, dir: &str) -> Result<Vec<Entry>, String>;
+    fn read(&self, key: &str) -> Result<Vec<u8>, String>;
+    /// Replaces `key` as a whole; readers never see half a file
+    fn write(&self, key: &str, data: &[u8]) -> Result<(), String>;
+    fn delete(&self, key: &str) -> Result<(), String>;
+    /// Whether `key` is there; backends with a cheaper check than listing override this
+    fn exists(&self, key: &str) -> Result<bool, String> {
+        let (dir, name) = key.rsplit_once('/').unwrap_or(("", key));
+        Ok(self.list(dir)?.iter().any(|e| e.name == name && !e.dir))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    pub dir: bool,
+}
+
+// file:///path (or a bare path), sftp://[user@]host[:port]/path, s3://bucket[/prefix]
+pub fn open(url: &str) -> Result<Box<dyn Backend>, String> {
+    if let Some(rest) = url.strip_prefix("sftp://") {
+        Ok(Box::new(crate::sftp::Sftp::connect(rest)?))
+    } else if let Some(rest) = url.strip_prefix("s3://") {
+        Ok(Box::new(crate::s3::S3::new(rest)?))
+    } else if url.contains("://") && !url.starts_with("file://") {
+        Err(format!("unsupported remote {} (use a path, sftp:// or s3://)", url))
+    } else {
+        Ok(Box::new(Local::new(url.strip_prefix("file://").unwrap_or(url))))
+    }
+}
+
+// A directory reachable through the filesystem: a NAS mount, a synced folder
+pub struct Local {
+    root: PathBuf,
+}
+
+impl Local {
+    pub fn new(root: impl Into<PathBuf>) -> Local {
+        Local { root: root.into() }
+    }
+}
+
+impl Backend for Local {
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+
+    fn list(&self, dir: &str) -> Result<Vec<Entry>, String> {
+        let Ok(entries) = std::fs::read_dir(self.root.join(dir)) else { return Ok(Vec::new()) };
+        Ok(entries.filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                Some(Entry { name: e.file_name().into_string().ok()?, size: meta.len(), dir: meta.is_dir() })
+            })
+            .collect())
+    }
+
+    fn read(&self, key: &str) -> Result<Vec<u8>, String> {
+        let path = self.root.join(key);
+        std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.root.join(key);
+        let err = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.root)).map_err(err)?;
+        let tmp = path.with_file_name(format!(".tmp-{}", std::process::id()));
+        std::fs::write(&tmp, data).map_err(err)?;
+        std::fs::rename(&tmp, &path).map_err(err)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let path = self.root.join(key);
+        std::fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        // Drop the snapshot folder once its manifest is gone; fails harmlessly if not empty
+        if let Some(parent) = path.parent().filter(|p| *p != self.root) {
+            std::fs::remove_dir(parent).ok();
+        }
+        Ok(())
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, String> {
+        Ok(self.root.join(key).is_file())
+    }
+}
+
+// The key of a snapshot folder's manifest, plain or sealed; None for any other folder
+pub fn manifest_key(b: &dyn Backend, name: &str) -> Result<Option<String>, String> {
+    let files = b.list(name)?;
+    Ok([MANIFEST, SEALED_MANIFEST].into_iter()
+        .find(|m| files.iter().any(|f| !f.dir && f.name == *m))
+        .map(|m| format!("{}/{}", name, m)))
+}
+
+// Snapshot names on the remote, from folders and archive files at the top
+pub fn names(remote: &dyn Backend) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = remote.list("")?
+        .into_iter()
+        .filter(|e| !e.name.starts_with('.'))
+        .filter_map(|e| if e.dir { Some(e.name) } else { archive::name_of(&e.name).map(str::to_string) })
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+// Every object hash the remote already holds
+fn remote_objects(remote: &dyn Backend) -> Result<HashSet<String>, String> {
+    let mut hashes = HashSet::new();
+    for shard in remote.list(OBJECTS)?.into_iter().filter(|e| e.dir) {
+        let objects = remote.list(&format!("{}/{}", OBJECTS, shard.name))?;
+        hashes.extend(objects.into_iter().filter(|e| !e.dir && !e.name.starts_with('.')).map(|e| e.name));
+    }
+    Ok(hashes)
+}
+
+// The encryption header has to match on both sides, or one side's objects are unreadable
+fn sync_header(root: &Path, remote: &dyn Backend) -> Result<(), String> {
+    let local = std::fs::read(root.join(crypto::HEADER)).ok();
+    let theirs = remote.list("")?.iter().any(|e| e.name == crypto::HEADER)
+        .then(|| remote.read(crypto::HEADER)).transpose()?;
+    match (local, theirs) {
+        (Some(a), Some(b)) if a != b => Err(format!("{} and {} are encrypted with different keys",
+            root.display(), remote.describe())),
+        (Some(a), None) => remote.write(crypto::HEADER, &a),
+        (None, Some(b)) if store::stored_size(root) == 0 => {
+            std::fs::create_dir_all(root).map_err(|e| e.to_string())?;
+            std::fs::write(root.join(crypto::HEADER), b).map_err(|e| e.to_string())
+        }
+        (None, Some(_)) => Err(format!("{} is encrypted but {} is not", remote.describe(), root.display())),
+        _ => Ok(()),
+    }
+}
+
+// Uploads a local snapshot: missing objects first, the manifest last, so a remote
+// snapshot is never visible before its content. Returns the bytes sent.
+pub fn push(root: &Path, name: &str, remote: &dyn Backend) -> Result<u64, String> {
+    sync_header(root, remote)?;
+    let snap = Snapshot::load(root, name)?;
+    let local = Local::new(root);
+    if let Some(path) = &snap.archive {
+        let file = path.file_name().and_then(|f| f.to_str()).unwrap_or(name);
+        let data = local.read(file)?;
+        remote.write(file, &data)?;
+        return Ok(data.len() as u64);
+    }
+    if snap.plain.is_some() {
+        return Err(format!("{} is a plain copy without a manifest; it stays local", name));
+    }
+
+    let have = remote_objects(remote)?;
+    let mut sent = 0u64;
+    let hashes: HashSet<&str> = snap.manifest.files.values().filter_map(|f| f.hash.as_deref()).collect();
+    for hash in hashes.into_iter().filter(|h| !have.contains(*h)) {
+        let data = local.read(&object_key(hash)?)?;
+        remote.write(&object_key(hash)?, &data)?;
+        sent += data.len() as u64;
+    }
+    let key = manifest_key(&local, name)?.ok_or_else(|| format!("{}: no manifest", name))?;
+    let manifest = local.read(&key)?;
+    remote.write(&key, &manifest)?;
+    Ok(sent + manifest.len() as u64)
+}
+
+// Copies the manifests (and archives) of remote snapshots the local root doesn't have,
+// so list, diff and restore see them. Object contents stay remote until needed.
+pub fn pull(root: &Path, remote: &dyn Backend) -> Result<Vec<String>, String> {
+    sync_header(root, remote)?;
+    let local: HashSet<String> = names(&Local::new(root))?.into_iter().collect();
+    let mut pulled = Vec::new();
+    for entry in remote.list("")?.into_iter().filter(|e| !e.name.starts_with('.')) {
+        let name = match (entry.dir, archive::name_of(&entry.name)) {
+            (true, _) => entry.name.clone(),
+            (false, Some(name)) => name.to_string(),
+            (false, None) => continue,
+        };
+        if local.contains(&name) {
+            continue;
+        }
+        if name.contains('/') || manifest::check_rel(&name).is_err() {
+            return Err(format!("{}: unsafe backup name {}", remote.describe(), name));
+        }
+        let key = match entry.dir {
+            true => match manifest_key(remote, &entry.name)? {
+                Some(key) => key,
+                None => continue,
+            },
+            false => entry.name.clone(),
+        };
+        let local = Local::new(root);
+        local.write(&key, &remote.read(&key)?)?;
+        // Loading checks every path the manifest lists; a tampered one doesn't stay
+        if let Err(e) = Snapshot::load(root, &name) {
+            local.delete(&key).ok();
+            return Err(format!("{}: {}", remote.describe(), e));
+        }
+        pulled.push(name);
+    }
+    Ok(pulled)
+}
+
+// Downloads the objects `snap` needs that aren't in the local store yet. Returns bytes fetched.
+pub fn fetch(root: &Path, snap: &Snapshot, remote: &dyn Backend) -> Result<u64, String> {
+    if snap.plain.is_some() || snap.archive.is_some() {
+        return Ok(0);
+    }
+    let local = Local::new(root);
+    let mut fetched = 0u64;
+    let hashes: HashSet<&str> = snap.manifest.files.values().filter_map(|f| f.hash.as_deref()).collect();
+    for hash in hashes {
+        let key = object_key(hash)?;
+        if local.exists(&key)? {
+            continue;
+        }
+        let data = remote.read(&key)?;
+        local.write(&key, &data)?;
+        fetched += data.len() as u64;
+    }
+    Ok(fetched)
+}
+
+// Every object the snapshots on the remote refer to, read from their manifests there:
+// a snapshot someone else pushed since our last pull counts too
+fn remote_referenced(root: &Path, remote: &dyn Backend, skip: &[String]) -> Result<HashSet<String>, String> {
+    let mut hashes = HashSet::new();
+    for entry in remote.list("")?.into_iter().filter(|e| e.dir && !e.name.starts_with('.') && !skip.contains(&e.name)) {
+        let Some(key) = manifest_key(remote, &entry.name)? else { continue };
+        let manifest = store::decode_manifest(root, &key, &remote.read(&key)?)
+            .map_err(|e| format!("{}: {}", key, e))?;
+        hashes.extend(manifest.files.into_values().filter_map(|f| f.hash));
+    }
+    Ok(hashes)
+}
+
+// Removes the pruned snapshots from the remote, then the objects no snapshot left there
+// (or kept locally) refers to. Returns the bytes freed there. With `Local::new(root)` as
+// the remote, this prunes the local root itself.
+pub fn prune(root: &Path, plan: &crate::prune::Plan, remote: &dyn Backend) -> Result<u64, String> {
+    let mut freed = 0u64;
+    let top = remote.list("")?;
+    for name in &plan.remove {
+        for entry in top.iter().filter(|e| !e.dir && archive::name_of(&e.name) == Some(name.as_str())) {
+            remote.delete(&entry.name)?;
+            freed += entry.size;
+        }
+        if top.iter().any(|e| e.dir && e.name == *name) {
+            for entry in remote.list(name)?.into_iter().filter(|e| !e.dir) {
+                remote.delete(&format!("{}/{}", name, entry.name))?;
+            }
+        }
+    }
+    let mut keep = remote_referenced(root, remote, &plan.remove)?;
+    keep.extend(store::referenced(root, &plan.keep)?);
+    for shard in remote.list(OBJECTS)?.into_iter().filter(|e| e.dir) {
+        for object in remote.list(&format!("{}/{}", OBJECTS, shard.name))? {
+            // Dot files are uploads still in flight
+            if !object.dir && !object.name.starts_with('.') && !keep.contains(&object.name) {
+                remote.delete(&format!("{}/{}/{}", OBJECTS, shard.name, object.name))?;
+                freed += object.size;
+            }
+        }
+    }
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn device() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("projects")).unwrap();
+        fs::write(dir.path().join("projects/project01.opz"), b"pattern data").unwrap();
+        fs::write(dir.path().join("bounce.aif"), b"bounce").unwrap();
+        dir
+    }
+
+    // Round-trips the operations every backend has to support
+    fn exercise(b: &dyn Backend) {
+        assert!(b.list("").unwrap().iter().all(|e| e.name != "snap"));
+        b.write("snap/manifest.json", b"{}").unwrap();
+        b.write(".objects/ab/abcd", b"data").unwrap();
+        b.write(".objects/ab/abcd", b"data!").unwrap();
+        assert_eq!(b.read(".objects/ab/abcd").unwrap(), b"data!");
+        assert!(b.exists(".objects/ab/abcd").unwrap());
+        assert!(!b.exists(".objects/ab/abce").unwrap() && !b.exists(".objects/ab").unwrap());
+
+        let top = b.list("").unwrap();
+        assert!(top.iter().any(|e| e.name == "snap" && e.dir));
+        assert_eq!(b.list(".objects/ab").unwrap(), vec![Entry { name: "abcd".into(), size: 5, dir: false }]);
+        assert!(b.list("missing").unwrap().is_empty());
+        assert!(b.read("missing/file").is_err());
+
+        b.delete("snap/manifest.json").unwrap();
+        b.delete(".objects/ab/abcd").unwrap();
+        assert!(names(b).unwrap().is_empty());
+    }
+
+    // ── Local / open ────────────────────────────────────────────────────────
+
+    #[test]
+    fn local_backend_behaves() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&Local::new(dir.path()));
+    }
+
+    #[test]
+    #[ignore = "needs an sshd: set OPZ_TEST_SFTP=sftp://user@localhost/tmp/opz-test"]
+    fn sftp_backend_behaves() {
+        exercise(open(&std::env::var("OPZ_TEST_SFTP").unwrap()).unwrap().as_ref());
+    }
+
+    #[test]
+    #[ignore = "needs MinIO: set OPZ_TEST_S3=s3://bucket/prefix plus AWS_* and AWS_ENDPOINT_URL"]
+    fn s3_backend_behaves() {
+        exercise(open(&std::env::var("OPZ_TEST_S3").unwrap()).unwrap().as_ref());
+    }
+
+    #[test]
+    fn open_picks_backend_by_scheme() {
+        assert_eq!(open("/mnt/nas/opz").unwrap().describe(), "/mnt/nas/opz");
+        assert_eq!(open("file:///mnt/nas/opz").unwrap().describe(), "/mnt/nas/opz");
+        assert!(open("ftp://host/opz").is_err());
+    }
+
+    // ── push / pull / fetch / prune ─────────────────────────────────────────
+
+    #[test]
+    fn push_then_pull_and_fetch_into_another_root() {
+        let src = device();
+        let laptop = tempfile::tempdir().unwrap();
+        let server = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let remote = Local::new(server.path());
+
+        store::store_snapshot(laptop.path(), src.path(), "2026-03-20_10-00-00").unwrap();
+        assert!(push(laptop.path(), "2026-03-20_10-00-00", &remote).unwrap() > 18);
+        // Objects already there aren't sent again
+        store::store_snapshot(laptop.path(), src.path(), "2026-03-21_10-00-00").unwrap();
+        let sent = push(laptop.path(), "2026-03-21_10-00-00", &remote).unwrap();
+        assert!(sent < 1000 && sent == fs::metadata(laptop.path().join("2026-03-21_10-00-00").join(MANIFEST)).unwrap().len());
+        assert_eq!(names(&remote).unwrap(), vec!["2026-03-20_10-00-00", "2026-03-21_10-00-00"]);
+
+        assert_eq!(pull(other.path(), &remote).unwrap().len(), 2);
+        assert!(pull(other.path(), &remote).unwrap().is_empty());
+        let snap = Snapshot::load(other.path(), "2026-03-21_10-00-00").unwrap();
+        assert_eq!(fetch(other.path(), &snap, &remote).unwrap(), 18);
+
+        let dst = tempfile::tempdir().unwrap();
+        store::checkout(other.path(), &snap, dst.path()).unwrap();
+        assert_eq!(fs::read(dst.path().join("bounce.aif")).unwrap(), b"bounce");
+    }
+
+    #[test]
+    fn pull_refuses_a_tampered_manifest() {
+        let root = tempfile::tempdir().unwrap();
+        let server = tempfile::tempdir().unwrap();
+        let remote = Local::new(server.path());
+        remote.write("2026-03-20_10-00-00/manifest.json", br#"{"files":{"../../.bashrc":{"size":4,"hash":null}}}"#).unwrap();
+        let e = pull(root.path(), &remote).unwrap_err();
+        assert!(e.contains("unsafe path"), "{}", e);
+        assert!(names(&Local::new(root.path())).unwrap().is_empty());
+
+        remote.delete("2026-03-20_10-00-00/manifest.json").unwrap();
+        remote.write("2026-03-21_10-00-00/manifest.json", br#"{"files":{"a":{"size":4,"hash":"../../x"}}}"#).unwrap();
+        let e = pull(root.path(), &remote).unwrap_err();
+        assert!(e.contains("bad content hash"), "{}", e);
+        assert!(names(&Local::new(root.path())).unwrap().is_empty());
+
+        // A manifest built in memory gets the same check before anything is written
+        let dst = tempfile::tempdir().unwrap();
+        let mut manifest = crate::manifest::Manifest::default();
+        manifest.files.insert("../escaped".into(), crate::manifest::FileEntry { size: 1, mtime: 0, hash: None });
+        let snap = Snapshot { name: "x".into(), manifest, plain: Some(dst.path().to_path_buf()), archive: None };
+        assert!(store::checkout(root.path(), &snap, &dst.path().join("out")).is_err());
+        assert!(!dst.path().join("escaped").exists());
+    }
+
+    #[test]
+    fn push_keeps_encryption_consistent() {
+        let src = device();
+        let laptop = tempfile::tempdir().unwrap();
+        let server = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        crypto::init(laptop.path(), b"pw", true).unwrap();
+        store::store_snapshot(laptop.path(), src.path(), "s").unwrap();
+
+        push(laptop.path(), "s", &Local::new(server.path())).unwrap();
+        assert!(server.path().join(crypto::HEADER).is_file());
+        pull(other.path(), &Local::new(server.path())).unwrap();
+        assert!(crypto::is_encrypted(other.path()));
+
+        // A differently keyed root can't mix with it
+        let third = tempfile::tempdir().unwrap();
+        crypto::init(third.path(), b"other", true).unwrap();
+        assert!(pull(third.path(), &Local::new(server.path())).is_err());
+    }
+
+    #[test]
+    fn prune_removes_snapshots_and_orphaned_objects_remotely() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        let server = tempfile::tempdir().unwrap();
+        let remote = Local::new(server.path());
+        store::store_snapshot(root.path(), src.path(), "2026-03-01_10-00-00").unwrap();
+        push(root.path(), "2026-03-01_10-00-00", &remote).unwrap();
+        fs::write(src.path().join("bounce.aif"), b"new bounce").unwrap();
+        store::store_snapshot(root.path(), src.path(), "2026-03-02_10-00-00").unwrap();
+        push(root.path(), "2026-03-02_10-00-00", &remote).unwrap();
+
+        let plan = crate::prune::Plan {
+            keep: vec!["2026-03-02_10-00-00".into()],
+            remove: vec!["2026-03-01_10-00-00".into()],
+            freed: 0,
+        };
+        assert!(prune(root.path(), &plan, &remote).unwrap() >= 6);
+        assert_eq!(names(&remote).unwrap(), vec!["2026-03-02_10-00-00"]);
+        assert_eq!(remote_objects(&remote).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn prune_keeps_objects_of_snapshots_only_the_remote_has() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        let bandmate = tempfile::tempdir().unwrap();
+        let server = tempfile::tempdir().unwrap();
+        let remote = Local::new(server.path());
+        store::store_snapshot(root.path(), src.path(), "2026-03-01_10-00-00").unwrap();
+        push(root.path(), "2026-03-01_10-00-00", &remote).unwrap();
+        // Pushed from elsewhere after our last pull; only its bounce differs
+        fs::write(src.path().join("bounce.aif"), b"their bounce").unwrap();
+        store::store_snapshot(bandmate.path(), src.path(), "2026-03-03_10-00-00").unwrap();
+        push(bandmate.path(), "2026-03-03_10-00-00", &remote).unwrap();
+
+        let plan = crate::prune::Plan { keep: vec![], remove: vec!["2026-03-01_10-00-00".into()], freed: 0 };
+        prune(root.path(), &plan, &remote).unwrap();
+        assert_eq!(names(&remote).unwrap(), vec!["2026-03-03_10-00-00"]);
+        let other = tempfile::tempdir().unwrap();
+        pull(other.path(), &remote).unwrap();
+        let snap = Snapshot::load(other.path(), "2026-03-03_10-00-00").unwrap();
+        fetch(other.path(), &snap, &remote).unwrap();
+        assert!(store::verify(other.path(), &snap).unwrap().corrupted.is_empty());
+    }
+}
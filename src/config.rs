@@ -0,0 +1,163 @@
+use crate::prune::Policy;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+// $XDG_CONFIG_HOME/opz-backup/config.toml (or ~/.config/...). Every key is optional:
+//
+//   root = "/Volumes/NAS/opz-backups"   # overridden by OPZ_BACKUP_ROOT, then --root
+//   device_name = "OP-Z"                # mount/volume name to look for
+//   verify = true                       # verify every backup (like --verify)
+//   incremental = true                  # only copy changed files (like --incremental)
+//   remote = "sftp://me@nas/srv/opz"    # also keep backups here (like --remote);
+//                                       # a path, sftp://user@host/path or s3://bucket/prefix
+//
+//   [retention]                         # defaults for `prune`
+//   keep_daily = 7
+//   keep_monthly = 12
+//   auto = true                         # prune after every backup
+//
+//   [encryption]
+//   enabled = true                      # encrypt new roots (needs an empty root)
+//   key_file = "~/.config/opz-backup/key" # passphrase file, instead of prompting
+//   plain_metadata = true               # keep manifests readable without the key
+//
+//   [s3]
+//   endpoint = "http://minio.local:9000" # S3-compatible server instead of AWS
+//   region = "eu-central-1"
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub root: Option<String>,
+    pub device_name: String,
+    pub verify: bool,
+    pub incremental: bool,
+    pub retention: Policy,
+    pub encryption: Encryption,
+    pub remote: Option<String>,
+    pub s3: S3,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Encryption {
+    pub enabled: bool,
+    pub key_file: Option<String>,
+    pub plain_metadata: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3 {
+    pub endpoint: Option<String>,
+    pub region: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { root: None, device_name: "OP-Z".to_string(), verify: false, incremental: false, retention: Policy::default(),
+            encryption: Encryption::default(), remote: None, s3: S3::default() }
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    let base = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("opz-backup").join("config.toml"))
+}
+
+// `~/` at the start of a configured path means $HOME
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
+
+impl Config {
+    // A missing file is fine (all defaults); a malformed one is an error
+    pub fn load() -> Result<Config, String> {
+        let Some(path) = config_path() else { return Ok(Config::default()) };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(_) => Ok(Config::default()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| e.message().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_empty_is_default() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parse_reads_all_keys() {
+        let c = Config::parse("root = \"/mnt/nas/opz\"\ndevice_name = \"OPZ\"\nverify = true\n").unwrap();
+        assert_eq!(c.root.as_deref(), Some("/mnt/nas/opz"));
+        assert_eq!(c.device_name, "OPZ");
+        assert!(c.verify);
+    }
+
+    #[test]
+    fn parse_reads_retention_table() {
+        let c = Config::parse("[retention]\nkeep_last = 3\nkeep_weekly = 4\nauto = true\n").unwrap();
+        assert_eq!(c.retention.keep_last, Some(3));
+        assert_eq!(c.retention.keep_weekly, Some(4));
+        assert_eq!(c.retention.keep_daily, None);
+        assert!(c.retention.auto);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_retention_keys() {
+        assert!(Config::parse("[retention]\nkeep_yearly = 1\n").is_err());
+    }
+
+    #[test]
+    fn parse_reads_encryption_table() {
+        let c = Config::parse("[encryption]\nenabled = true\nkey_file = \"~/key\"\n").unwrap();
+        assert!(c.encryption.enabled);
+        assert_eq!(c.encryption.key_file.as_deref(), Some("~/key"));
+        assert!(!c.encryption.plain_metadata);
+        assert!(Config::parse("[encryption]\ncipher = \"aes\"\n").is_err());
+    }
+
+    #[test]
+    fn parse_reads_remote_and_s3_table() {
+        let c = Config::parse("remote = \"s3://studio/opz\"\n[s3]\nendpoint = \"http://minio:9000\"\n").unwrap();
+        assert_eq!(c.remote.as_deref(), Some("s3://studio/opz"));
+        assert_eq!(c.s3.endpoint.as_deref(), Some("http://minio:9000"));
+        assert_eq!(c.s3.region, None);
+        assert!(Config::parse("[s3]\nbucket = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn parse_accepts_the_documented_example() {
+        let example: String = include_str!("config.rs").lines()
+            .skip_while(|l| !l.starts_with("//   root = "))
+            .take_while(|l| l.starts_with("//"))
+            .map(|l| format!("{}\n", &l[2..]))
+            .collect();
+        let c = Config::parse(&example).unwrap();
+        assert_eq!(c.remote.as_deref(), Some("sftp://me@nas/srv/opz"));
+        assert!(c.retention.auto && c.encryption.enabled);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys() {
+        assert!(Config::parse("roots = \"/x\"").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_wrong_types() {
+        assert!(Config::parse("verify = \"yes\"").is_err());
+    }
+}
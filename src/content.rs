@@ -0,0 +1,249 @@
+use crate::manifest::FileEntry;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// What the files on an OP-Z mean. Paths look like:
+//
+//   projects/project03.opz          project slots 1–16
+//   samplepacks/1-kick/07/*.aif     drum sample packs, tracks 1–4, slots 01–10
+//   synth/6-lead/02/*.aif           synth presets, tracks 5–8, slots 01–10
+//   bounce/*.wav                    bounced audio
+//   import/*, rejected/*            files waiting for (or refused by) the importer
+//   config/*.json                   device settings
+
+pub const PROJECT_SLOTS: u8 = 16;
+pub const PACK_SLOTS: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Track {
+    Kick = 1,
+    Snare,
+    Perc,
+    Fx,
+    Bass,
+    Lead,
+    Arp,
+    Chord,
+}
+
+impl Track {
+    pub const ALL: [Track; 8] =
+        [Track::Kick, Track::Snare, Track::Perc, Track::Fx, Track::Bass, Track::Lead, Track::Arp, Track::Chord];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Track::Kick => "kick",
+            Track::Snare => "snare",
+            Track::Perc => "perc",
+            Track::Fx => "fx",
+            Track::Bass => "bass",
+            Track::Lead => "lead",
+            Track::Arp => "arp",
+            Track::Chord => "chord",
+        }
+    }
+
+    pub fn is_drum(self) -> bool {
+        (self as u8) <= 4
+    }
+
+    // The folder under samplepacks/ or synth/, e.g. 1-kick
+    pub fn folder(self) -> String {
+        format!("{}-{}", self as u8, self.name())
+    }
+
+    fn from_folder(folder: &str) -> Option<Track> {
+        Track::ALL.into_iter().find(|t| t.folder() == folder)
+    }
+
+    fn from_name(name: &str) -> Option<Track> {
+        Track::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Item {
+    Project { slot: u8 },
+    SamplePack { track: Track, slot: u8 },
+    Synth { track: Track, slot: u8 },
+    Bounce { name: String },
+    Import { name: String },
+    Rejected { name: String },
+    Config { name: String },
+    /// Anything the OP-Z doesn't put there itself
+    Other { path: String },
+}
+
+// "03" → 3 when within 1..=max
+fn slot(s: &str, max: u8) -> Option<u8> {
+    s.parse().ok().filter(|n| (1..=max).contains(n))
+}
+
+impl Item {
+    // What a file in a backup belongs to
+    pub fn classify(rel: &str) -> Item {
+        let parts: Vec<&str> = rel.split('/').collect();
+        let other = || Item::Other { path: rel.to_string() };
+        match parts.as_slice() {
+            ["projects", file] => file.strip_prefix("project").and_then(|f| f.strip_suffix(".opz"))
+                .and_then(|n| slot(n, PROJECT_SLOTS))
+                .map_or_else(other, |slot| Item::Project { slot }),
+            [top @ ("samplepacks" | "synth"), folder, n, _, ..] => match (Track::from_folder(folder), slot(n, PACK_SLOTS)) {
+                (Some(track), Some(slot)) if track.is_drum() == (*top == "samplepacks") => match track.is_drum() {
+                    true => Item::SamplePack { track, slot },
+                    false => Item::Synth { track, slot },
+                },
+                _ => other(),
+            },
+            ["bounce", name, ..] => Item::Bounce { name: name.to_string() },
+            ["import", name, ..] => Item::Import { name: name.to_string() },
+            ["rejected", name, ..] => Item::Rejected { name: name.to_string() },
+            ["config", name] => Item::Config { name: name.to_string() },
+            _ => other(),
+        }
+    }
+
+    // "project 3", "project3", "kick 7", "kick slot 7", "lead 2"; None for anything else
+    pub fn parse(s: &str) -> Option<Item> {
+        let s = s.trim().to_ascii_lowercase();
+        let split = s.find(|c: char| c.is_ascii_digit())?;
+        let (word, n) = s.split_at(split);
+        let word = word.trim().trim_end_matches("slot").trim();
+        let n: u8 = n.trim().parse().ok()?;
+        if word == "project" {
+            return (1..=PROJECT_SLOTS).contains(&n).then_some(Item::Project { slot: n });
+        }
+        let track = Track::from_name(word)?;
+        let slot = (1..=PACK_SLOTS).contains(&n).then_some(n)?;
+        Some(match track.is_drum() {
+            true => Item::SamplePack { track, slot },
+            false => Item::Synth { track, slot },
+        })
+    }
+
+    // The file or folder holding the item, usable with `path_matches`
+    pub fn path(&self) -> String {
+        match self {
+            Item::Project { slot } => format!("projects/project{:02}.opz", slot),
+            Item::SamplePack { track, slot } => format!("samplepacks/{}/{:02}", track.folder(), slot),
+            Item::Synth { track, slot } => format!("synth/{}/{:02}", track.folder(), slot),
+            Item::Bounce { name } => format!("bounce/{}", name),
+            Item::Import { name } => format!("import/{}", name),
+            Item::Rejected { name } => format!("rejected/{}", name),
+            Item::Config { name } => format!("config/{}", name),
+            Item::Other { path } => path.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Project { slot } => write!(f, "project {}", slot),
+            Item::SamplePack { track, slot } | Item::Synth { track, slot } => write!(f, "{} slot {}", track.name(), slot),
+            Item::Bounce { name } => write!(f, "bounce {}", name),
+            Item::Import { name } => write!(f, "import {}", name),
+            Item::Rejected { name } => write!(f, "rejected {}", name),
+            Item::Config { name } => match name.strip_suffix(".json") {
+                Some(stem @ ("midi" | "dmx" | "cv")) => write!(f, "{} config", stem.to_ascii_uppercase()),
+                Some(stem) => write!(f, "{} config", stem),
+                None => write!(f, "config {}", name),
+            },
+            Item::Other { path } => write!(f, "{}", path),
+        }
+    }
+}
+
+// A restore selector: a content name like "project 3" becomes its path, anything else is
+// taken as a path already
+pub fn selector(sel: &str) -> String {
+    Item::parse(sel).map_or_else(|| sel.to_string(), |item| item.path())
+}
+
+// The files of a backup grouped by what they belong to
+pub fn items(files: &BTreeMap<String, FileEntry>) -> BTreeMap<Item, Vec<&str>> {
+    let mut items: BTreeMap<Item, Vec<&str>> = BTreeMap::new();
+    for rel in files.keys() {
+        items.entry(Item::classify(rel)).or_default().push(rel);
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> FileEntry {
+        FileEntry { size: 1, mtime: 0, hash: None }
+    }
+
+    // ── classify ────────────────────────────────────────────────────────────
+
+    #[test]
+    fn classify_slots() {
+        assert_eq!(Item::classify("projects/project03.opz"), Item::Project { slot: 3 });
+        assert_eq!(Item::classify("projects/project16.opz"), Item::Project { slot: 16 });
+        assert_eq!(Item::classify("samplepacks/1-kick/07/808.aif"), Item::SamplePack { track: Track::Kick, slot: 7 });
+        assert_eq!(Item::classify("samplepacks/4-fx/10/noise.aif"), Item::SamplePack { track: Track::Fx, slot: 10 });
+        assert_eq!(Item::classify("synth/6-lead/02/saw.aif"), Item::Synth { track: Track::Lead, slot: 2 });
+    }
+
+    #[test]
+    fn classify_folders_by_name() {
+        assert_eq!(Item::classify("bounce/take1.wav"), Item::Bounce { name: "take1.wav".into() });
+        assert_eq!(Item::classify("import/loops/a.wav"), Item::Import { name: "loops".into() });
+        assert_eq!(Item::classify("rejected/bad.mp3"), Item::Rejected { name: "bad.mp3".into() });
+        assert_eq!(Item::classify("config/general.json"), Item::Config { name: "general.json".into() });
+    }
+
+    #[test]
+    fn classify_unknown_paths_as_other() {
+        for path in ["projects/project17.opz", "projects/notes.txt", "samplepacks/1-kick/11/a.aif",
+            "samplepacks/5-bass/01/a.aif", "synth/1-kick/01/a.aif", "samplepacks/1-kick/01", ".DS_Store"] {
+            assert_eq!(Item::classify(path), Item::Other { path: path.into() }, "{}", path);
+        }
+    }
+
+    // ── parse / path / Display ──────────────────────────────────────────────
+
+    #[test]
+    fn parse_accepts_spoken_names() {
+        assert_eq!(Item::parse("project 3"), Some(Item::Project { slot: 3 }));
+        assert_eq!(Item::parse("Project3"), Some(Item::Project { slot: 3 }));
+        assert_eq!(Item::parse("kick slot 7"), Some(Item::SamplePack { track: Track::Kick, slot: 7 }));
+        assert_eq!(Item::parse("chord 10"), Some(Item::Synth { track: Track::Chord, slot: 10 }));
+        assert_eq!(Item::parse("project 17"), None);
+        assert_eq!(Item::parse("kick 0"), None);
+        assert_eq!(Item::parse("projects/project03.opz"), None);
+    }
+
+    #[test]
+    fn path_and_display_roundtrip() {
+        for name in ["project 3", "kick slot 7", "lead slot 2"] {
+            let item = Item::parse(name).unwrap();
+            assert_eq!(item.to_string(), name);
+            let file = format!("{}/x.aif", item.path());
+            let classified = if let Item::Project { .. } = item { Item::classify(&item.path()) } else { Item::classify(&file) };
+            assert_eq!(classified, item);
+        }
+        assert_eq!(Item::Config { name: "midi.json".into() }.to_string(), "MIDI config");
+        assert_eq!(Item::Config { name: "general.json".into() }.to_string(), "general config");
+        assert_eq!(selector("snare 4"), "samplepacks/2-snare/04");
+        assert_eq!(selector("projects/"), "projects/");
+    }
+
+    // ── items ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn items_groups_files() {
+        let files: BTreeMap<String, FileEntry> = ["samplepacks/1-kick/01/a.aif", "samplepacks/1-kick/01/b.aif",
+            "projects/project01.opz"].into_iter().map(|p| (p.to_string(), entry())).collect();
+        let items = items(&files);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[&Item::SamplePack { track: Track::Kick, slot: 1 }].len(), 2);
+        assert_eq!(items.keys().next(), Some(&Item::Project { slot: 1 }));
+    }
+}
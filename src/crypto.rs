@@ -0,0 +1,207 @@
+use crate::config::{Config, expand_home};
+use crate::store::{OBJECTS, hex};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// An encrypted root has this file next to its snapshots. Once present, every object
+// (and, unless plain_metadata is set, every manifest) is sealed with XChaCha20-Poly1305
+// under a key derived from the passphrase with Argon2id.
+pub const HEADER: &str = ".encryption.json";
+
+// Where the passphrase comes from when no key file is configured and there's no prompt
+pub const KEY_ENV: &str = "OPZ_BACKUP_KEY";
+
+// Encrypted known plaintext: decrypting it tells a wrong passphrase from a damaged object
+const CHECK: &[u8] = b"opz-backup";
+const NONCE_LEN: usize = 24;
+
+pub type Key = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    kdf: String,
+    salt: String,
+    check: String,
+    /// Manifests stay readable without the key, so `list` and `status` work locked
+    plain_metadata: bool,
+}
+
+// Unlocked keys per root, so the passphrase is asked for once per run
+static KEYS: Mutex<BTreeMap<PathBuf, Key>> = Mutex::new(BTreeMap::new());
+
+fn header(root: &Path) -> Result<Option<Header>, String> {
+    let path = root.join(HEADER);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).map(Some).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(_) => Ok(None),
+    }
+}
+
+pub fn is_encrypted(root: &Path) -> bool {
+    root.join(HEADER).is_file()
+}
+
+// Manifests are sealed unless the root was set up with plain metadata
+pub fn seals_metadata(root: &Path) -> bool {
+    matches!(header(root), Ok(Some(h)) if !h.plain_metadata)
+}
+
+fn derive(secret: &[u8], salt: &[u8]) -> Result<Key, String> {
+    let mut key = [0u8; 32];
+    Argon2::default().hash_password_into(secret, salt, &mut key).map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>, String> {
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()).ok_or_else(|| format!("bad hex in {}", HEADER)))
+        .collect()
+}
+
+// nonce || ciphertext+tag. `aad` (the object hash or snapshot name) ties the bytes to
+// where they are stored, so swapping two files is caught like any other tampering.
+pub fn seal(key: &Key, aad: &[u8], plain: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = XChaCha20Poly1305::new(key.into())
+        .encrypt(&nonce, Payload { msg: plain, aad })
+        .map_err(|_| "encryption failed".to_string())?;
+    Ok([nonce.as_slice(), &sealed].concat())
+}
+
+pub fn open(key: &Key, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN {
+        return Err("truncated".to_string());
+    }
+    let (nonce, body) = sealed.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: body, aad })
+        .map_err(|_| "wrong key or damaged data".to_string())
+}
+
+// Turns an empty root into an encrypted one. Refuses when unencrypted objects are
+// already there: new backups would silently share them.
+pub fn init(root: &Path, secret: &[u8], plain_metadata: bool) -> Result<(), String> {
+    if is_encrypted(root) {
+        return Err(format!("{} is already encrypted", root.display()));
+    }
+    if std::fs::read_dir(root.join(OBJECTS)).is_ok_and(|mut d| d.next().is_some()) {
+        return Err(format!("{} already holds unencrypted backups; encrypt into a new root", root.display()));
+    }
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive(secret, &salt)?;
+    let header = Header {
+        version: 1,
+        kdf: "argon2id".to_string(),
+        salt: hex(&salt),
+        check: hex(&seal(&key, HEADER.as_bytes(), CHECK)?),
+        plain_metadata,
+    };
+    std::fs::create_dir_all(root).map_err(|e| e.to_string())?;
+    let text = serde_json::to_string_pretty(&header).map_err(|e| e.to_string())?;
+    std::fs::write(root.join(HEADER), text).map_err(|e| e.to_string())?;
+    KEYS.lock().unwrap().insert(root.to_path_buf(), key);
+    Ok(())
+}
+
+pub fn unlock_with(root: &Path, secret: &[u8]) -> Result<Key, String> {
+    let header = header(root)?.ok_or_else(|| format!("{} is not encrypted", root.display()))?;
+    let key = derive(secret, &unhex(&header.salt)?)?;
+    open(&key, HEADER.as_bytes(), &unhex(&header.check)?)
+        .map_err(|_| format!("wrong passphrase for {}", root.display()))?;
+    KEYS.lock().unwrap().insert(root.to_path_buf(), key);
+    Ok(key)
+}
+
+// The root's key, or None when it isn't encrypted. Unlocks on first use.
+pub fn key(root: &Path) -> Result<Option<Key>, String> {
+    if !is_encrypted(root) {
+        return Ok(None);
+    }
+    if let Some(key) = KEYS.lock().unwrap().get(root) {
+        return Ok(Some(*key));
+    }
+    unlock_with(root, &secret(false)?).map(Some)
+}
+
+// OPZ_BACKUP_KEY, then `key_file` from the config, then a prompt
+pub fn secret(confirm: bool) -> Result<Vec<u8>, String> {
+    if let Ok(secret) = std::env::var(KEY_ENV) && !secret.is_empty() {
+        return Ok(secret.into_bytes());
+    }
+    if let Some(file) = Config::load()?.encryption.key_file {
+        let path = expand_home(&file);
+        let bytes = std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+        return Ok(bytes.trim_ascii().to_vec());
+    }
+    let theme = dialoguer::theme::ColorfulTheme::default();
+    let mut prompt = dialoguer::Password::with_theme(&theme).with_prompt("Backup passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Repeat passphrase", "Passphrases don't match");
+    }
+    prompt.interact().map(String::into_bytes).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ── seal / open ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn seal_roundtrips_and_detects_tampering() {
+        let key = [7u8; 32];
+        let sealed = seal(&key, b"aa", b"kick kick").unwrap();
+        assert_eq!(open(&key, b"aa", &sealed).unwrap(), b"kick kick");
+        assert!(open(&[8u8; 32], b"aa", &sealed).is_err());
+        assert!(open(&key, b"bb", &sealed).is_err());
+
+        let mut flipped = sealed.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(open(&key, b"aa", &flipped).is_err());
+        assert!(open(&key, b"aa", &sealed[..10]).is_err());
+    }
+
+    #[test]
+    fn seal_uses_fresh_nonces() {
+        let key = [7u8; 32];
+        assert_ne!(seal(&key, b"", b"x").unwrap(), seal(&key, b"", b"x").unwrap());
+    }
+
+    // ── init / unlock_with ──────────────────────────────────────────────────
+
+    #[test]
+    fn init_then_unlock_checks_passphrase() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(!is_encrypted(root.path()));
+        init(root.path(), b"hunter2", true).unwrap();
+        assert!(is_encrypted(root.path()));
+        assert!(!seals_metadata(root.path()));
+
+        let key = key(root.path()).unwrap().unwrap();
+        assert_eq!(unlock_with(root.path(), b"hunter2").unwrap(), key);
+        assert!(unlock_with(root.path(), b"hunter3").unwrap_err().contains("wrong passphrase"));
+        assert!(init(root.path(), b"again", false).is_err());
+    }
+
+    #[test]
+    fn init_refuses_root_with_plain_objects() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join(OBJECTS).join("ab")).unwrap();
+        assert!(init(root.path(), b"pw", false).is_err());
+        assert!(!is_encrypted(root.path()));
+    }
+
+    #[test]
+    fn key_is_none_for_plain_root() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(key(root.path()).unwrap(), None);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Teenage Engineering's USB vendor ID and the OP-Z's product ID
+pub const VENDOR_ID: u16 = 0x2367;
+pub const PRODUCT_ID: u16 = 0x000c;
+
+// Top-level folders the OP-Z exposes in content (disk) mode
+pub const LAYOUT: &[&str] = &["config", "projects", "samplepacks", "synth"];
+
+// Folders holding the user's own material; config/, import/ and rejected/ are managed
+// by the device and never mirrored away
+pub const CONTENT: &[&str] = &["bounce", "projects", "samplepacks", "synth"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub source: String,
+    pub point: String,
+}
+
+pub fn has_opz_layout(dir: &Path) -> bool {
+    LAYOUT.iter().all(|d| dir.join(d).is_dir())
+}
+
+// mountinfo writes space, tab, newline and backslash as \ooo (octal)
+fn unescape_octal(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        let digits = b.get(i + 1..i + 4).filter(|d| d.iter().all(|c| (b'0'..=b'7').contains(c)));
+        match (b[i], digits) {
+            (b'\\', Some(d)) => {
+                out.push((d[0] - b'0') * 64 + (d[1] - b'0') * 8 + (d[2] - b'0'));
+                i += 4;
+            }
+            (c, _) => { out.push(c); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// udev writes unsafe characters in /dev/disk/by-label names as \xNN
+fn unescape_hex(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&c, tail)) = rest.split_first() {
+        let hex = tail.strip_prefix(b"x")
+            .and_then(|t| t.get(..2))
+            .and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (c, hex) {
+            (b'\\', Some(byte)) => { out.push(byte); rest = &tail[3..]; }
+            _ => { out.push(c); rest = tail; }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// /proc/self/mountinfo: `id parent maj:min root point opts [optional...] - fstype source superopts`
+pub fn parse_mountinfo(text: &str) -> Vec<Mount> {
+    text.lines()
+        .filter_map(|l| {
+            let fields: Vec<&str> = l.split(' ').collect();
+            let sep = fields.iter().position(|f| *f == "-")?;
+            Some(Mount {
+                source: unescape_octal(fields.get(sep + 2)?),
+                point: unescape_octal(fields.get(4)?),
+            })
+        })
+        .collect()
+}
+
+// Block device name (sdb1) → filesystem label, from the /dev/disk/by-label symlinks
+pub fn labels(by_label: &Path) -> HashMap<String, String> {
+    let Ok(entries) = std::fs::read_dir(by_label) else { return HashMap::new() };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let target = std::fs::read_link(e.path()).ok()?;
+            let dev = target.file_name()?.to_str()?.to_string();
+            Some((dev, unescape_hex(e.file_name().to_str()?)))
+        })
+        .collect()
+}
+
+// USB vendor/product of the device behind a block device, found by walking up its sysfs path
+pub fn usb_ids(sys: &Path, dev: &str) -> Option<(u16, u16)> {
+    let path = std::fs::canonicalize(sys.join("class").join("block").join(dev)).ok()?;
+    let read = |p: PathBuf| -> Option<u16> {
+        u16::from_str_radix(std::fs::read_to_string(p).ok()?.trim(), 16).ok()
+    };
+    path.ancestors()
+        .find_map(|a| Some((read(a.join("idVendor"))?, read(a.join("idProduct"))?)))
+}
+
+// Best mount that passes the layout check: USB IDs beat the volume label, which beats
+// a mount path merely containing the name.
+pub fn pick(
+    mounts: &[Mount],
+    name: &str,
+    labels: &HashMap<String, String>,
+    usb: impl Fn(&str) -> Option<(u16, u16)>,
+    layout: impl Fn(&Path) -> bool,
+) -> Option<String> {
+    mounts.iter()
+        .filter_map(|m| {
+            let dev = m.source.strip_prefix("/dev/").unwrap_or("");
+            let rank = if !dev.is_empty() && usb(dev) == Some((VENDOR_ID, PRODUCT_ID)) {
+                0
+            } else if labels.get(dev).is_some_and(|l| l == name) {
+                1
+            } else if m.point.contains(name) {
+                2
+            } else {
+                return None;
+            };
+            Some((rank, m))
+        })
+        .filter(|(_, m)| layout(Path::new(&m.point)))
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, m)| m.point.clone())
+}
+
+// First mount point in `df -P` output containing name. The mount point is everything
+// after the capacity column, so paths with spaces survive.
+pub fn find_opz<'a>(df: &'a str, name: &str) -> Option<&'a str> {
+    df.lines()
+        .skip(1)
+        .filter_map(|l| l.split_once("% ").map(|(_, p)| p.trim()))
+        .find(|p| p.contains(name))
+}
+
+pub fn opz_mount(name: &str) -> Result<String, String> {
+    if let Ok(text) = std::fs::read_to_string("/proc/self/mountinfo") {
+        let labels = labels(Path::new("/dev/disk/by-label"));
+        let usb = |dev: &str| usb_ids(Path::new("/sys"), dev);
+        if let Some(point) = pick(&parse_mountinfo(&text), name, &labels, usb, has_opz_layout) {
+            return Ok(point);
+        }
+    }
+
+    // No procfs (macOS) or nothing found there: fall back to df
+    let output = Command::new("df").arg("-P").output().map_err(|e| e.to_string())?;
+    let df = String::from_utf8(output.stdout).map_err(|e| e.to_string())?;
+    match find_opz(&df, name) {
+        Some(point) if has_opz_layout(Path::new(point)) => Ok(point.to_string()),
+        Some(point) => Err(format!("{} doesn't look like an OP-Z (expected folders: {})", point, LAYOUT.join(", "))),
+        None => Err("No OP-Z (turn off, press I, turn on, plug USB)".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const DF_WITH_OPZ: &str = include_str!("../fixtures/df-macos-opz.txt");
+    const DF_WITHOUT_OPZ: &str = include_str!("../fixtures/df-macos-no-opz.txt");
+    const DF_SPACES: &str = include_str!("../fixtures/df-linux-spaces.txt");
+    const MOUNTINFO_OPZ: &str = include_str!("../fixtures/mountinfo-linux-opz.txt");
+    const MOUNTINFO_LABEL: &str = include_str!("../fixtures/mountinfo-linux-label.txt");
+
+    // ── find_opz ────────────────────────────────────────────────────────────
+
+    #[test]
+    fn find_opz_returns_mount_path() {
+        assert_eq!(find_opz(DF_WITH_OPZ, "OP-Z"), Some("/Volumes/OP-Z"));
+    }
+
+    #[test]
+    fn find_opz_returns_none_when_not_mounted() {
+        assert_eq!(find_opz(DF_WITHOUT_OPZ, "OP-Z"), None);
+    }
+
+    #[test]
+    fn find_opz_skips_header_row() {
+        let df = "Filesystem 1 2 3 4 /Volumes/OP-Z\n\
+                  /dev/disk1 100 50 50 50% /normal";
+        assert_eq!(find_opz(df, "OP-Z"), None);
+    }
+
+    #[test]
+    fn find_opz_matches_on_mount_column_not_filesystem() {
+        let df = "Filesystem     1024-blocks      Used  Available Capacity Mounted on\n\
+                  /dev/OP-Z/s1   244277232   94712304  148564528      39% /other";
+        assert_eq!(find_opz(df, "OP-Z"), None);
+    }
+
+    #[test]
+    fn find_opz_returns_first_match_when_multiple() {
+        let df = "Filesystem     1024-blocks      Used  Available Capacity Mounted on\n\
+                  /dev/disk2s1     1953520    1953520          0     100% /Volumes/OP-Z\n\
+                  /dev/disk3s1     1953520    1953520          0     100% /Volumes/OP-Z-2";
+        assert_eq!(find_opz(df, "OP-Z"), Some("/Volumes/OP-Z"));
+    }
+
+    #[test]
+    fn find_opz_uses_configured_name() {
+        let df = "Filesystem     1024-blocks      Used  Available Capacity Mounted on\n\
+                  /dev/sdb1        1953520    1953520          0     100% /media/me/OPZ-STUDIO";
+        assert_eq!(find_opz(df, "OPZ-STUDIO"), Some("/media/me/OPZ-STUDIO"));
+        assert_eq!(find_opz(df, "OP-Z"), None);
+    }
+
+    #[test]
+    fn find_opz_empty_input() {
+        assert_eq!(find_opz("", "OP-Z"), None);
+    }
+
+    #[test]
+    fn find_opz_header_only() {
+        assert_eq!(find_opz("Filesystem 1024-blocks Used Available Capacity Mounted on", "OP-Z"), None);
+    }
+
+    #[test]
+    fn find_opz_keeps_spaces_in_mount_point() {
+        assert_eq!(find_opz(DF_SPACES, "OP-Z"), Some("/media/me/My OP-Z"));
+    }
+
+    // ── parse_mountinfo ─────────────────────────────────────────────────────
+
+    #[test]
+    fn parse_mountinfo_reads_point_and_source() {
+        let mounts = parse_mountinfo(MOUNTINFO_OPZ);
+        assert_eq!(mounts.len(), 5);
+        assert_eq!(mounts[4], Mount { source: "/dev/sdb1".into(), point: "/media/me/OP-Z".into() });
+        assert_eq!(mounts[1].source, "proc");
+    }
+
+    #[test]
+    fn parse_mountinfo_unescapes_spaces() {
+        let mounts = parse_mountinfo(MOUNTINFO_LABEL);
+        assert_eq!(mounts[1].point, "/mnt/usb stick");
+        assert_eq!(mounts[2].point, "/media/me/OP-Z backup");
+    }
+
+    #[test]
+    fn parse_mountinfo_skips_malformed_lines() {
+        assert!(parse_mountinfo("garbage\n\n").is_empty());
+    }
+
+    #[test]
+    fn unescape_hex_decodes_udev_labels() {
+        assert_eq!(unescape_hex("OP-Z\\x20STUDIO"), "OP-Z STUDIO");
+        assert_eq!(unescape_hex("plain"), "plain");
+        assert_eq!(unescape_hex("bad\\xZZ"), "bad\\xZZ");
+    }
+
+    // ── pick ────────────────────────────────────────────────────────────────
+
+    fn no_usb(_: &str) -> Option<(u16, u16)> { None }
+
+    #[test]
+    fn pick_requires_opz_layout() {
+        let mounts = parse_mountinfo(MOUNTINFO_OPZ);
+        let layout = |p: &Path| p == Path::new("/media/me/OP-Z");
+        // /srv/OP-Z is an unrelated bind mount that happens to share the name
+        assert_eq!(pick(&mounts, "OP-Z", &HashMap::new(), no_usb, layout), Some("/media/me/OP-Z".into()));
+        assert_eq!(pick(&mounts, "OP-Z", &HashMap::new(), no_usb, |_| false), None);
+    }
+
+    #[test]
+    fn pick_matches_volume_label() {
+        let mounts = parse_mountinfo(MOUNTINFO_LABEL);
+        let labels = HashMap::from([("sdc1".to_string(), "OP-Z".to_string())]);
+        // The label beats /media/me/OP-Z backup, whose path merely contains the name
+        assert_eq!(pick(&mounts, "OP-Z", &labels, no_usb, |_| true), Some("/mnt/usb stick".into()));
+    }
+
+    #[test]
+    fn pick_prefers_usb_ids() {
+        let mounts = parse_mountinfo(MOUNTINFO_LABEL);
+        let usb = |dev: &str| (dev == "sdd1").then_some((VENDOR_ID, PRODUCT_ID));
+        let labels = HashMap::from([("sdc1".to_string(), "OP-Z".to_string())]);
+        assert_eq!(pick(&mounts, "OP-Z", &labels, usb, |_| true), Some("/media/me/OP-Z backup".into()));
+    }
+
+    // ── usb_ids / labels / has_opz_layout ───────────────────────────────────
+
+    #[cfg(unix)]
+    #[test]
+    fn usb_ids_walks_up_sysfs() {
+        let sys = tempfile::tempdir().unwrap();
+        let usb = sys.path().join("devices/usb1/1-1");
+        let part = usb.join("1-1:1.0/host6/block/sdb/sdb1");
+        fs::create_dir_all(&part).unwrap();
+        fs::write(usb.join("idVendor"), "2367\n").unwrap();
+        fs::write(usb.join("idProduct"), "000c\n").unwrap();
+        fs::create_dir_all(sys.path().join("class/block")).unwrap();
+        std::os::unix::fs::symlink(&part, sys.path().join("class/block/sdb1")).unwrap();
+
+        assert_eq!(usb_ids(sys.path(), "sdb1"), Some((VENDOR_ID, PRODUCT_ID)));
+        assert_eq!(usb_ids(sys.path(), "sdz9"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn labels_maps_devices_to_unescaped_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink("../../sdb1", dir.path().join("OP-Z\\x20STUDIO")).unwrap();
+        assert_eq!(labels(dir.path()).get("sdb1").map(String::as_str), Some("OP-Z STUDIO"));
+        assert!(labels(Path::new("/nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn has_opz_layout_checks_top_level_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!has_opz_layout(dir.path()));
+        for d in LAYOUT {
+            fs::create_dir(dir.path().join(d)).unwrap();
+        }
+        assert!(has_opz_layout(dir.path()));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::aiff::{self, Pack};
+use crate::content::{self, Item};
+use crate::settings;
+use crate::manifest::FileEntry;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Change {
+    Added { path: String, size: u64 },
+    Removed { path: String, size: u64 },
+    Modified { path: String, old_size: u64, new_size: u64 },
+}
+
+// Same-size edits (every projects/*.opz has a fixed size) only show up by content, so
+// hashes win; without them on both sides we fall back to size + mtime.
+pub fn differs(a: &FileEntry, b: &FileEntry) -> bool {
+    match (&a.hash, &b.hash) {
+        (Some(x), Some(y)) => x != y,
+        _ => a.size != b.size || (a.mtime != 0 && b.mtime != 0 && a.mtime != b.mtime),
+    }
+}
+
+// Removals first, then additions and modifications, each in path order
+pub fn compare(a: &BTreeMap<String, FileEntry>, b: &BTreeMap<String, FileEntry>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (path, old) in a {
+        if !b.contains_key(path) {
+            changes.push(Change::Removed { path: path.clone(), size: old.size });
+        }
+    }
+    for (path, new) in b {
+        match a.get(path) {
+            None => changes.push(Change::Added { path: path.clone(), size: new.size }),
+            Some(old) if differs(old, new) => changes.push(Change::Modified {
+                path: path.clone(),
+                old_size: old.size,
+                new_size: new.size,
+            }),
+            _ => {}
+        }
+    }
+    changes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verb {
+    Added,
+    Removed,
+    Modified,
+    /// A sample slot now holds different files
+    Replaced,
+}
+
+// One change in OP-Z terms: "project 5 modified", "kick slot 3 replaced"
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemChange {
+    pub item: Item,
+    pub change: Verb,
+    /// For replaced slots: file names only in the older side
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub old: Vec<String>,
+    /// For replaced slots: file names only in the newer side
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub new: Vec<String>,
+    /// For drum packs: the pack name when added, the changed settings when modified
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub detail: Vec<String>,
+    /// For config files: each changed setting, "midi.channel_track_3: 3 → 10"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub settings: Vec<String>,
+}
+
+impl ItemChange {
+    fn new(item: Item, change: Verb) -> ItemChange {
+        ItemChange { item, change, old: Vec::new(), new: Vec::new(), detail: Vec::new(), settings: Vec::new() }
+    }
+}
+
+impl fmt::Display for ItemChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verb = match (self.change, &self.item) {
+            (Verb::Modified, Item::Config { .. }) => "changed",
+            (Verb::Added, _) => "added",
+            (Verb::Removed, _) => "removed",
+            (Verb::Modified, _) => "modified",
+            (Verb::Replaced, _) => "replaced",
+        };
+        write!(f, "{} {}", self.item, verb)?;
+        match (self.old.is_empty(), self.new.is_empty()) {
+            (true, true) => Ok(()),
+            (false, true) => write!(f, " ({} removed)", self.old.join(", ")),
+            (true, false) => write!(f, " ({} added)", self.new.join(", ")),
+            _ => write!(f, " ({} → {})", self.old.join(", "), self.new.join(", ")),
+        }?;
+        match self.detail.is_empty() {
+            true => Ok(()),
+            false => write!(f, " ({})", self.detail.join(", ")),
+        }
+    }
+}
+
+fn file_name(rel: &str) -> String {
+    rel.rsplit('/').next().unwrap_or(rel).to_string()
+}
+
+// The changes between two trees grouped by project, slot and config file, in slot order
+pub fn summarize(a: &BTreeMap<String, FileEntry>, b: &BTreeMap<String, FileEntry>) -> Vec<ItemChange> {
+    let (before, after) = (content::items(a), content::items(b));
+    let changed: BTreeSet<Item> = compare(a, b).iter()
+        .map(|c| match c {
+            Change::Added { path, .. } | Change::Removed { path, .. } | Change::Modified { path, .. } => Item::classify(path),
+        })
+        .collect();
+
+    changed.into_iter().map(|item| {
+        let (old, new) = match (before.get(&item), after.get(&item)) {
+            (None, _) => return ItemChange::new(item, Verb::Added),
+            (_, None) => return ItemChange::new(item, Verb::Removed),
+            (Some(old), Some(new)) => (old, new),
+        };
+        let old: BTreeSet<String> = old.iter().map(|rel| file_name(rel)).collect();
+        let new: BTreeSet<String> = new.iter().map(|rel| file_name(rel)).collect();
+        let slot = matches!(item, Item::SamplePack { .. } | Item::Synth { .. });
+        match slot && old != new {
+            true => ItemChange {
+                change: Verb::Replaced,
+                old: old.difference(&new).cloned().collect(),
+                new: new.difference(&old).cloned().collect(),
+                detail: Vec::new(),
+                settings: Vec::new(),
+                item,
+            },
+            false => ItemChange::new(item, Verb::Modified),
+        }
+    }).collect()
+}
+
+// The pack file of a drum slot on one side
+fn pack_file<'a>(files: &'a BTreeMap<String, FileEntry>, item: &Item) -> Option<&'a str> {
+    let dir = item.path();
+    files.keys().map(String::as_str).find(|rel| crate::store::path_matches(rel, &dir) && rel.to_ascii_lowercase().ends_with(".aif"))
+}
+
+// Adds what the AIFF metadata says to drum slot changes: the name and slice count of an
+// added pack, the settings edited in a modified one. `old` and `new` read a file's pack.
+pub fn describe_packs(summary: &mut [ItemChange], a: &BTreeMap<String, FileEntry>, b: &BTreeMap<String, FileEntry>,
+    old: impl Fn(&str) -> Option<Pack>, new: impl Fn(&str) -> Option<Pack>) {
+    for change in summary.iter_mut().filter(|c| matches!(c.item, Item::SamplePack { .. })) {
+        let after = pack_file(b, &change.item).and_then(&new);
+        change.detail = match (change.change, after) {
+            (Verb::Added | Verb::Replaced, Some(pack)) => vec![format!("\"{}\", {} slices", pack.name, pack.slices)],
+            (Verb::Modified, Some(pack)) => match pack_file(a, &change.item).and_then(&old) {
+                Some(before) => aiff::changed_settings(&before, &pack),
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+    }
+}
+
+// Lists the settings that changed in modified config files. `old` and `new` read a file's
+// bytes; files that aren't JSON keep the plain "changed".
+pub fn describe_settings(summary: &mut [ItemChange], old: impl Fn(&str) -> Option<Vec<u8>>, new: impl Fn(&str) -> Option<Vec<u8>>) {
+    for change in summary.iter_mut().filter(|c| c.change == Verb::Modified && matches!(c.item, Item::Config { .. })) {
+        let path = change.item.path();
+        let flat = |bytes: Option<Vec<u8>>| settings::flatten(&path, &bytes?).ok();
+        if let (Some(a), Some(b)) = (flat(old(&path)), flat(new(&path))) {
+            change.settings = settings::compare(&a, &b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: u64, mtime: i64, hash: Option<&str>) -> FileEntry {
+        FileEntry { size, mtime, hash: hash.map(str::to_string) }
+    }
+
+    fn tree(entries: &[(&str, FileEntry)]) -> BTreeMap<String, FileEntry> {
+        entries.iter().map(|(p, e)| (p.to_string(), e.clone())).collect()
+    }
+
+    // ── differs ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn differs_detects_same_size_edit_by_hash() {
+        assert!(differs(&file(100, 1, Some("aa")), &file(100, 1, Some("bb"))));
+    }
+
+    #[test]
+    fn differs_ignores_mtime_when_hashes_match() {
+        assert!(!differs(&file(100, 1, Some("aa")), &file(100, 2, Some("aa"))));
+    }
+
+    #[test]
+    fn differs_falls_back_to_mtime_and_size() {
+        assert!(differs(&file(100, 1, None), &file(100, 2, Some("aa"))));
+        assert!(differs(&file(100, 1, None), &file(101, 1, None)));
+        assert!(!differs(&file(100, 1, None), &file(100, 1, None)));
+    }
+
+    #[test]
+    fn differs_ignores_unknown_mtime() {
+        assert!(!differs(&file(100, 0, None), &file(100, 5, None)));
+    }
+
+    // ── compare ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn compare_reports_added_removed_modified() {
+        let a = tree(&[
+            ("gone.aif", file(1, 1, Some("g"))),
+            ("projects/project01.opz", file(10, 1, Some("p1"))),
+            ("same.aif", file(2, 1, Some("s"))),
+        ]);
+        let b = tree(&[
+            ("new.aif", file(3, 2, Some("n"))),
+            ("projects/project01.opz", file(10, 2, Some("p2"))),
+            ("same.aif", file(2, 2, Some("s"))),
+        ]);
+
+        assert_eq!(compare(&a, &b), vec![
+            Change::Removed { path: "gone.aif".into(), size: 1 },
+            Change::Added { path: "new.aif".into(), size: 3 },
+            Change::Modified { path: "projects/project01.opz".into(), old_size: 10, new_size: 10 },
+        ]);
+    }
+
+    #[test]
+    fn compare_identical_trees_is_empty() {
+        let a = tree(&[("a", file(1, 1, Some("x")))]);
+        assert!(compare(&a, &a.clone()).is_empty());
+    }
+
+    // ── summarize ───────────────────────────────────────────────────────────
+
+    #[test]
+    fn summarize_speaks_in_slots() {
+        let a = tree(&[
+            ("config/midi.json", file(5, 1, Some("m1"))),
+            ("projects/project05.opz", file(10, 1, Some("p1"))),
+            ("samplepacks/1-kick/03/808.aif", file(3, 1, Some("k1"))),
+            ("samplepacks/2-snare/01/clap.aif", file(3, 1, Some("s1"))),
+        ]);
+        let b = tree(&[
+            ("config/midi.json", file(6, 2, Some("m2"))),
+            ("projects/project05.opz", file(10, 2, Some("p2"))),
+            ("samplepacks/1-kick/03/punchy.aif", file(4, 2, Some("k2"))),
+            ("samplepacks/2-snare/01/clap.aif", file(3, 1, Some("s1"))),
+            ("synth/6-lead/02/saw.aif", file(2, 2, Some("l1"))),
+        ]);
+
+        let lines: Vec<String> = summarize(&a, &b).iter().map(ItemChange::to_string).collect();
+        assert_eq!(lines, vec![
+            "project 5 modified",
+            "kick slot 3 replaced (808.aif → punchy.aif)",
+            "lead slot 2 added",
+            "MIDI config changed",
+        ]);
+    }
+
+    #[test]
+    fn summarize_same_files_with_new_content_is_modified() {
+        let a = tree(&[("samplepacks/3-perc/02/a.aif", file(3, 1, Some("x"))), ("notes.txt", file(1, 1, Some("n")))]);
+        let b = tree(&[("samplepacks/3-perc/02/a.aif", file(3, 1, Some("y")))]);
+        let summary = summarize(&a, &b);
+        assert_eq!(summary[0].change, Verb::Modified);
+        assert_eq!(summary[1], ItemChange::new(Item::Other { path: "notes.txt".into() }, Verb::Removed));
+    }
+
+    #[test]
+    fn describe_packs_adds_names_and_settings() {
+        use crate::aiff::tests::{KIT, aiff};
+        let a = tree(&[("samplepacks/1-kick/01/a.aif", file(3, 1, Some("x")))]);
+        let b = tree(&[
+            ("samplepacks/1-kick/01/a.aif", file(3, 1, Some("y"))),
+            ("samplepacks/2-snare/04/b.aif", file(3, 1, Some("z"))),
+        ]);
+        let pack = |hash: &str| {
+            let json = if hash == "x" { KIT.to_string() } else { KIT.replace("\"octave\":0", "\"octave\":1") };
+            crate::aiff::parse(&aiff(&json, 10)).ok()
+        };
+        let mut summary = summarize(&a, &b);
+        describe_packs(&mut summary, &a, &b, |rel| pack(a[rel].hash.as_deref()?), |rel| pack(b[rel].hash.as_deref()?));
+        let lines: Vec<String> = summary.iter().map(ItemChange::to_string).collect();
+        assert_eq!(lines, vec!["kick slot 1 modified (octave)", "snare slot 4 added (\"808 kit\", 3 slices)"]);
+    }
+
+    #[test]
+    fn describe_settings_compares_config_keys() {
+        let a = tree(&[("config/midi.json", file(5, 1, Some("x"))), ("config/dmx.json", file(1, 1, Some("d1")))]);
+        let b = tree(&[("config/midi.json", file(6, 1, Some("y"))), ("config/dmx.json", file(1, 1, Some("d2")))]);
+        let read = |hash: &str| match hash {
+            "x" => Some(br#"{"channel_track_3": 3}"#.to_vec()),
+            "y" => Some(br#"{"channel_track_3": 10}"#.to_vec()),
+            _ => Some(b"\x00".to_vec()),
+        };
+        let mut summary = summarize(&a, &b);
+        describe_settings(&mut summary, |rel| read(a[rel].hash.as_deref()?), |rel| read(b[rel].hash.as_deref()?));
+        assert_eq!(summary[0].item, Item::Config { name: "dmx.json".into() });
+        assert!(summary[0].settings.is_empty());
+        assert_eq!(summary[1].settings, vec!["midi.channel_track_3: 3 → 10"]);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::archive;
+use crate::detect::{LAYOUT, has_opz_layout};
+use crate::manifest::Manifest;
+use crate::store::{self, Snapshot};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+// Turns a folder (e.g. a Finder drag-and-drop of the OP-Z), a zip of one, or an
+// exported archive into a regular snapshot in the store. Returns its name and the
+// bytes newly stored.
+pub fn import(root: &Path, src: &Path, date: Option<&str>) -> Result<(String, u64), String> {
+    if src.is_dir() {
+        return import_dir(root, src, src, date);
+    }
+    static SCRATCH: AtomicUsize = AtomicUsize::new(0);
+    let n = SCRATCH.fetch_add(1, Ordering::Relaxed);
+    let scratch = std::env::temp_dir().join(format!("opz-import-{}-{}", std::process::id(), n));
+    let result = unpack(src, &scratch).and_then(|()| import_dir(root, &scratch, src, date));
+    std::fs::remove_dir_all(&scratch).ok();
+    result
+}
+
+fn import_dir(root: &Path, dir: &Path, src: &Path, date: Option<&str>) -> Result<(String, u64), String> {
+    let opz = find_layout(dir).ok_or_else(|| format!("{} doesn't look like an OP-Z backup (expected folders: {})",
+        src.display(), LAYOUT.join(", ")))?;
+    let time = match date {
+        Some(date) => parse_date(date)?,
+        None => newest_mtime(&store::scan(&opz)).ok_or_else(|| format!("{} has no files", src.display()))?,
+    };
+    let name = time.format(store::NAME_FORMAT).to_string();
+    if Snapshot::load(root, &name).is_ok() {
+        return Err(format!("a backup named {} already exists (pick another --date)", name));
+    }
+
+    let bytes = store::store_snapshot(root, &opz, &name)?;
+    let mut snap = Snapshot::load(root, &name)?;
+    snap.manifest.source = src.to_string_lossy().into_owned();
+    snap.manifest.tag = Some("import".to_string());
+    snap.save_manifest(root)?;
+    Ok((name, bytes))
+}
+
+// The OP-Z folders sit in dir itself or, when the whole volume was copied, in its
+// only subfolder
+pub fn find_layout(dir: &Path) -> Option<PathBuf> {
+    if has_opz_layout(dir) {
+        return Some(dir.to_path_buf());
+    }
+    let subdirs: Vec<PathBuf> = std::fs::read_dir(dir).ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|e| !e.file_name().to_string_lossy().starts_with(['.', '_']))
+        .map(|e| e.path())
+        .collect();
+    match subdirs.as_slice() {
+        [only] if has_opz_layout(only) => Some(only.clone()),
+        _ => None,
+    }
+}
+
+// --date takes a day (2024-05-01), a day and time (2024-05-01T18:30, 2024-05-01 18:30:15)
+// or a snapshot name
+pub fn parse_date(s: &str) -> Result<NaiveDateTime, String> {
+    let s = s.trim();
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", store::NAME_FORMAT] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, format) {
+            return Ok(t);
+        }
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|d| d.and_hms_opt(12, 0, 0).unwrap())
+        .map_err(|_| format!("bad date {} (expected e.g. 2024-05-01 or 2024-05-01T18:30)", s))
+}
+
+// When the copy was made, as best we can tell: the newest file in it
+fn newest_mtime(manifest: &Manifest) -> Option<NaiveDateTime> {
+    let secs = manifest.files.values().map(|f| f.mtime).filter(|&t| t > 0).max()?;
+    Some(Local.timestamp_opt(secs, 0).single()?.naive_local())
+}
+
+// Our own archives keep their manifest's mtimes; any other zip is unpacked as is
+fn unpack(src: &Path, dst: &Path) -> Result<(), String> {
+    let kind = archive::Kind::of(src);
+    match (kind, kind.and_then(|_| archive::read_manifest(src).ok())) {
+        (Some(_), Some(manifest)) => {
+            archive::extract(src, &manifest, dst)?;
+            // Archives hold files only; an OP-Z always has these folders, empty or not
+            LAYOUT.iter().try_for_each(|d| std::fs::create_dir_all(dst.join(d)).map_err(|e| e.to_string()))
+        }
+        (Some(archive::Kind::Zip), None) => unzip(src, dst),
+        _ => Err(format!("{}: expected a folder, a .zip or a .tar.zst", src.display())),
+    }
+}
+
+// Extracts every file with its zip timestamp, so the date can be guessed from it.
+// macOS resource forks (__MACOSX/) are left out.
+fn unzip(src: &Path, dst: &Path) -> Result<(), String> {
+    let err = |e: &dyn std::fmt::Display| format!("{}: {}", src.display(), e);
+    let file = BufReader::new(File::open(src).map_err(|e| err(&e))?);
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| err(&e))?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| err(&e))?;
+        let Some(rel) = entry.enclosed_name() else { continue };
+        let out = dst.join(&rel);
+        if rel.starts_with("__MACOSX") {
+            continue;
+        }
+        if entry.is_dir() {
+            std::fs::create_dir_all(&out).map_err(|e| err(&e))?;
+            continue;
+        }
+        std::fs::create_dir_all(out.parent().unwrap_or(dst)).map_err(|e| err(&e))?;
+        let mut file = File::create(&out).map_err(|e| err(&e))?;
+        std::io::copy(&mut entry, &mut file).map_err(|e| err(&e))?;
+        if let Some(secs) = entry.last_modified().and_then(zip_time) {
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(secs)).map_err(|e| err(&e))?;
+        }
+    }
+    Ok(())
+}
+
+// Zip timestamps are local wall-clock time
+fn zip_time(t: zip::DateTime) -> Option<u64> {
+    let naive = NaiveDate::from_ymd_opt(t.year().into(), t.month().into(), t.day().into())?
+        .and_hms_opt(t.hour().into(), t.minute().into(), t.second().into())?;
+    u64::try_from(Local.from_local_datetime(&naive).earliest()?.timestamp()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    fn opz_copy(dir: &Path) {
+        for d in LAYOUT {
+            fs::create_dir_all(dir.join(d)).unwrap();
+        }
+        fs::write(dir.join("projects/project01.opz"), b"pattern data").unwrap();
+        let f = File::options().write(true).open(dir.join("projects/project01.opz")).unwrap();
+        let t = Local.with_ymd_and_hms(2021, 6, 5, 14, 30, 0).unwrap();
+        f.set_modified(UNIX_EPOCH + Duration::from_secs(t.timestamp() as u64)).unwrap();
+    }
+
+    // ── find_layout / parse_date ────────────────────────────────────────────
+
+    #[test]
+    fn find_layout_accepts_dir_or_single_subfolder() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(find_layout(dir.path()), None);
+
+        opz_copy(&dir.path().join("OP-Z"));
+        fs::create_dir(dir.path().join("__MACOSX")).unwrap();
+        assert_eq!(find_layout(dir.path()), Some(dir.path().join("OP-Z")));
+        assert_eq!(find_layout(&dir.path().join("OP-Z")), Some(dir.path().join("OP-Z")));
+
+        opz_copy(&dir.path().join("OP-Z copy"));
+        assert_eq!(find_layout(dir.path()), None);
+    }
+
+    #[test]
+    fn parse_date_formats() {
+        let t = |s| parse_date(s).unwrap().format(store::NAME_FORMAT).to_string();
+        assert_eq!(t("2024-05-01"), "2024-05-01_12-00-00");
+        assert_eq!(t("2024-05-01T18:30"), "2024-05-01_18-30-00");
+        assert_eq!(t("2024-05-01 18:30:15"), "2024-05-01_18-30-15");
+        assert_eq!(t("2024-05-01_18-30-15"), "2024-05-01_18-30-15");
+        assert!(parse_date("May 1st").is_err());
+    }
+
+    // ── import ──────────────────────────────────────────────────────────────
+
+    #[test]
+    fn import_dir_dates_snapshot_from_newest_file() {
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        opz_copy(src.path());
+
+        let (name, bytes) = import(root.path(), src.path(), None).unwrap();
+        assert_eq!(name, "2021-06-05_14-30-00");
+        assert_eq!(bytes, 12);
+        let snap = Snapshot::load(root.path(), &name).unwrap();
+        assert_eq!(snap.manifest.tag.as_deref(), Some("import"));
+        assert!(snap.manifest.files["projects/project01.opz"].hash.is_some());
+
+        // Same date again is refused rather than overwritten
+        assert!(import(root.path(), src.path(), None).is_err());
+        let (name, _) = import(root.path(), src.path(), Some("2021-06-06")).unwrap();
+        assert_eq!(name, "2021-06-06_12-00-00");
+    }
+
+    #[test]
+    fn import_rejects_non_opz_folder() {
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("notes.txt"), b"hi").unwrap();
+        let e = import(root.path(), src.path(), None).unwrap_err();
+        assert!(e.contains("doesn't look like an OP-Z"));
+    }
+
+    #[test]
+    fn import_zip_keeps_entry_times() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let path = dir.path().join("from-bandmate.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .last_modified_time(zip::DateTime::from_date_and_time(2022, 3, 4, 9, 15, 0).unwrap());
+        for d in LAYOUT {
+            zip.add_directory(format!("OP-Z/{}/", d), options).unwrap();
+        }
+        zip.start_file("OP-Z/projects/project02.opz", options).unwrap();
+        zip.write_all(b"notes").unwrap();
+        zip.start_file("__MACOSX/OP-Z/._project02.opz", options).unwrap();
+        zip.write_all(b"fork").unwrap();
+        zip.finish().unwrap();
+
+        let (name, _) = import(root.path(), &path, None).unwrap();
+        assert_eq!(name, "2022-03-04_09-15-00");
+        let snap = Snapshot::load(root.path(), &name).unwrap();
+        assert_eq!(snap.manifest.files.keys().collect::<Vec<_>>(), vec!["projects/project02.opz"]);
+    }
+
+    #[test]
+    fn import_exported_archive_uses_its_manifest() {
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        opz_copy(src.path());
+        archive::snapshot(out.path(), src.path(), "x", archive::Kind::Zstd).unwrap();
+
+        let (name, _) = import(root.path(), &out.path().join("x.tar.zst"), None).unwrap();
+        assert_eq!(name, "2021-06-05_14-30-00");
+    }
+
+    #[test]
+    fn import_never_writes_outside_scratch_for_a_malicious_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let path = dir.path().join("from-bandmate.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file(crate::manifest::MANIFEST, options).unwrap();
+        zip.write_all(br#"{"files":{"../opz-escaped":{"size":3},"projects/project01.opz":{"size":1}}}"#).unwrap();
+        zip.start_file("files/../opz-escaped", options).unwrap();
+        zip.write_all(b"pwn").unwrap();
+        zip.start_file("files/projects/project01.opz", options).unwrap();
+        zip.write_all(b"x").unwrap();
+        zip.finish().unwrap();
+
+        assert!(archive::read_manifest(&path).unwrap_err().contains("unsafe path"));
+        assert!(import(root.path(), &path, None).is_err());
+        // ../ from the scratch folder import unpacks into
+        assert!(!std::env::temp_dir().join("opz-escaped").exists());
+    }
+}
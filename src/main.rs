@@ -1,10 +1,34 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use config::Config;
 use fs_extra::dir::{CopyOptions, TransitProcess, copy_with_progress};
 use human_bytes::human_bytes;
 use ml_progress::progress;
 use std::collections::BTreeMap;
 use std::path::Path;
 use std::process::Command;
+use std::sync::OnceLock;
+use diff::Change;
+use manifest::{PRE_RESTORE, PROJECT_TAG};
+use output::{BackupRecord, DiffRecord, Format, PackRecord, StatusRecord};
+use store::Snapshot;
+
+mod aiff;
+mod archive;
+mod backend;
+mod config;
+mod content;
+mod crypto;
+mod detect;
+mod diff;
+mod import;
+mod manifest;
+mod output;
+mod plan;
+mod prune;
+mod s3;
+mod settings;
+mod sftp;
+mod store;
 
 #[derive(Parser)]
 #[command(
@@ -14,33 +38,181 @@ use std::process::Command;
 struct Cli {
     #[command(subcommand)]
     command: Option<Cmd>,
+    /// After backing up, re-read the OP-Z and the backup and compare them file by file
+    #[arg(long)]
+    verify: bool,
+    /// Only copy files whose size or mtime changed since the last backup
+    #[arg(long)]
+    incremental: bool,
+    /// Back up even when the OP-Z still matches the latest backup
+    #[arg(long)]
+    force: bool,
+    /// Write the backup as a single compressed archive instead of into the object store
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    archive: Option<archive::Kind>,
+    /// Backup folder (overrides OPZ_BACKUP_ROOT and the config file)
+    #[arg(long, global = true)]
+    root: Option<String>,
+    /// Also keep backups here: a path, sftp://user@host/path or s3://bucket/prefix
+    #[arg(long, global = true, value_name = "URL")]
+    remote: Option<String>,
+    /// Output format for list, status, diff and config show
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
 }
 
 #[derive(Subcommand)]
 enum Cmd {
     /// List all existing backups with their sizes
-    List,
+    List {
+        /// Also show the drum packs in each backup: name and slice count per slot
+        #[arg(long)]
+        detail: bool,
+        /// Only backups holding a drum pack whose name contains this (implies --detail)
+        #[arg(long, value_name = "NAME")]
+        pack: Option<String>,
+    },
     /// Interactively select and restore a backup to the OP-Z
-    Restore,
-    /// Show what changed between the two most recent backups
-    Diff,
+    Restore(RestoreArgs),
+    /// Put back the OP-Z state saved automatically before the last restore
+    UndoRestore {
+        /// Show what would change on the OP-Z and exit
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show what changed between two backups (default: the two most recent)
+    ///
+    /// Each side is a backup name, a name prefix or date (latest match wins),
+    /// `latest`, `latest~N` (N backups before the latest) or `device` for the
+    /// connected OP-Z.
+    Diff {
+        /// Older side (default: the backup before the newest)
+        a: Option<String>,
+        /// Newer side (default: latest)
+        b: Option<String>,
+        /// List every changed file instead of summarizing by project, slot and config
+        #[arg(long)]
+        raw: bool,
+    },
     /// Show OP-Z connection state and last backup info
     Status,
     /// Open a backup folder in Finder / file manager
     Open,
     /// Watch for OP-Z connection and auto-backup on plug-in
     Watch,
+    /// Delete old backups according to a retention policy
+    ///
+    /// Without --keep-* flags the `[retention]` table of the config file is used.
+    Prune {
+        #[command(flatten)]
+        policy: prune::Policy,
+        /// Show what would be removed and how much space it frees, without deleting
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Pack a backup into a single .tar.zst or .zip file (format from the extension)
+    Export {
+        /// Backup to export: name, date prefix or latest~N
+        backup: String,
+        /// Archive to write, e.g. opz.tar.zst or opz.zip
+        file: std::path::PathBuf,
+        /// Export from an encrypted root anyway; the archive is written in the clear
+        #[arg(long)]
+        decrypt: bool,
+    },
+    /// Add a manual OP-Z copy (folder, .zip or exported archive) to the backups
+    Import {
+        /// Folder or archive holding the OP-Z folders (config, projects, samplepacks, synth)
+        path: std::path::PathBuf,
+        /// When the copy was made, e.g. 2024-05-01 or 2024-05-01T18:30 (default: newest file's mtime)
+        #[arg(long)]
+        date: Option<String>,
+    },
+    /// Upload backups the remote doesn't have yet (e.g. after a failed upload)
+    Push,
+    /// Save or restore a single project slot, leaving the rest of the OP-Z alone
+    Project {
+        #[command(subcommand)]
+        action: ProjectCmd,
+    },
+    /// Inspect the OP-Z settings (config/*.json) stored in a backup
+    Config {
+        #[command(subcommand)]
+        action: ConfigCmd,
+    },
+    /// Re-hash a backup and check it against its manifest (default: latest)
+    Verify {
+        /// Backup to verify: name, date prefix or latest~N
+        #[arg(conflicts_with = "all")]
+        backup: Option<String>,
+        /// Verify every backup
+        #[arg(long)]
+        all: bool,
+    },
 }
 
-fn hb(n: u64) -> String { human_bytes(n as f64) }
+#[derive(Subcommand)]
+enum ProjectCmd {
+    /// Back up one project slot, e.g. before experimenting with it
+    Save {
+        /// Project slot, 1–16
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=content::PROJECT_SLOTS as i64))]
+        slot: u8,
+        /// A name for this save, shown in `list`
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Put one project slot back from a backup or project save
+    Restore {
+        /// Project slot, 1–16
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=content::PROJECT_SLOTS as i64))]
+        slot: u8,
+        /// Backup to take it from: name, date prefix or latest~N (default: the newest holding the slot)
+        #[arg(long, value_name = "BACKUP")]
+        from: Option<String>,
+        /// Write it into this slot instead of the one it came from
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=content::PROJECT_SLOTS as i64))]
+        to_slot: Option<u8>,
+        /// Show what would change on the OP-Z and exit
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
 
-fn find_opz(df: &str) -> Option<&str> {
-    df.lines()
-        .skip(1)
-        .filter_map(|l| l.split_whitespace().nth(5))
-        .find(|p| p.contains("OP-Z"))
+#[derive(Subcommand)]
+enum ConfigCmd {
+    /// Print every setting of a backup as dotted keys, e.g. midi.channel_track_3
+    Show {
+        /// Backup: name, date prefix, latest~N or `device` (default: latest)
+        backup: Option<String>,
+    },
 }
 
+#[derive(Args)]
+struct RestoreArgs {
+    /// Backup to restore: name, date prefix or latest~N (default: pick interactively)
+    backup: Option<String>,
+    /// Restore only this file, folder or slot (repeatable), e.g. projects/project03.opz,
+    /// "project 3" or "kick 7"
+    #[arg(long, value_name = "PATH")]
+    only: Vec<String>,
+    /// Pick files and folders to restore from the backup's tree
+    #[arg(long, conflicts_with = "only")]
+    select: bool,
+    /// Skip re-reading the OP-Z after the copy to check every file arrived intact
+    #[arg(long)]
+    no_verify: bool,
+    /// Show what would change on the OP-Z and exit (default backup: latest)
+    #[arg(long)]
+    dry_run: bool,
+    /// Also delete files in the OP-Z content folders that are not in the backup
+    #[arg(long)]
+    mirror: bool,
+}
+
+fn hb(n: u64) -> String { human_bytes(n as f64) }
+
+#[allow(clippy::manual_checked_ops)]
 fn pct(copied: u64, total: u64) -> u8 {
     if total == 0 { 0 } else { (copied * 100 / total) as u8 }
 }
@@ -65,45 +237,153 @@ fn backup_copy(src: &str, dst: &str, overwrite: bool) -> Result<u64, String> {
     Ok(bytes)
 }
 
+// Set once from --root
+static ROOT_FLAG: OnceLock<String> = OnceLock::new();
+
+// --root, then OPZ_BACKUP_ROOT, then `root` in the config file, then ~/opz-backups
 fn backup_root() -> String {
+    if let Some(root) = ROOT_FLAG.get() {
+        return root.clone();
+    }
+    if let Ok(root) = std::env::var("OPZ_BACKUP_ROOT") && !root.is_empty() {
+        return root;
+    }
+    if let Some(root) = Config::load().unwrap_or_default().root {
+        return config::expand_home(&root);
+    }
     let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
     format!("{}/opz-backups", home)
 }
 
+// Set once from --remote
+static REMOTE_FLAG: OnceLock<String> = OnceLock::new();
+
+// --remote, then `remote` in the config file; None keeps backups local only
+fn remote() -> Result<Option<Box<dyn backend::Backend>>, String> {
+    let url = match REMOTE_FLAG.get() {
+        Some(url) => Some(url.clone()),
+        None => Config::load()?.remote.map(|url| config::expand_home(&url)),
+    };
+    url.map(|url| backend::open(&url)).transpose()
+}
+
+// Brings in the manifests of backups that only exist on the remote. The local root
+// still works when the remote is unreachable, so that's a warning, not an error.
+fn pull_remote() -> Option<Box<dyn backend::Backend>> {
+    let remote = remote().unwrap_or_else(|e| { eprintln!("! remote: {}", e); None })?;
+    if let Err(e) = pull_from(remote.as_ref()) {
+        eprintln!("! {}", e);
+    }
+    Some(remote)
+}
+
+fn pull_from(remote: &dyn backend::Backend) -> Result<(), String> {
+    let pulled = backend::pull(Path::new(&backup_root()), remote).map_err(|e| format!("{}: {}", remote.describe(), e))?;
+    if !pulled.is_empty() {
+        println!("← {} backup{} from {}", pulled.len(), if pulled.len() == 1 { "" } else { "s" }, remote.describe());
+    }
+    Ok(())
+}
+
+// Downloads the content a remote-only backup needs before it's read locally
+fn fetch_remote(remote: Option<&dyn backend::Backend>, snap: &Snapshot) -> Result<(), String> {
+    let Some(remote) = remote else { return Ok(()) };
+    let bytes = backend::fetch(Path::new(&backup_root()), snap, remote)?;
+    if bytes > 0 {
+        println!("← {} from {}", hb(bytes), remote.describe());
+    }
+    Ok(())
+}
+
+// pull_remote for commands that also print JSON or TSV: there, nothing but the
+// output itself may reach stdout
+fn remote_for(format: Format) -> Option<Box<dyn backend::Backend>> {
+    if format == Format::Text {
+        return pull_remote();
+    }
+    let remote = remote().ok().flatten()?;
+    backend::pull(Path::new(&backup_root()), remote.as_ref()).ok();
+    Some(remote)
+}
+
+fn fetch_for(format: Format, remote: Option<&dyn backend::Backend>, snap: &Snapshot) -> Result<(), String> {
+    match (format, remote) {
+        (Format::Text, _) => fetch_remote(remote, snap),
+        (_, Some(remote)) => backend::fetch(Path::new(&backup_root()), snap, remote).map(drop),
+        (_, None) => Ok(()),
+    }
+}
+
 fn backup_names() -> Result<Vec<String>, String> {
     let root = backup_root();
-    let mut names: Vec<String> = std::fs::read_dir(&root)
-        .map_err(|_| format!("no backups in {}", root))?
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
-        .filter_map(|e| e.file_name().into_string().ok())
-        .collect();
-    names.sort();
-    Ok(names)
+    if !Path::new(&root).is_dir() {
+        return Err(format!("no backups in {}", root));
+    }
+    backend::names(&backend::Local::new(&root))
+}
+
+// Resolves a backup selector against sorted names: exact name, `latest`, `latest~N`,
+// or a prefix such as a date (the newest match wins).
+fn select_backup<'a>(names: &'a [String], sel: &str) -> Result<&'a str, String> {
+    if let Some(name) = names.iter().find(|n| *n == sel) {
+        return Ok(name);
+    }
+    if let Some(back) = sel.strip_prefix("latest") {
+        let n: usize = match back.strip_prefix('~') {
+            Some(n) => n.parse().map_err(|_| format!("bad selector {}", sel))?,
+            None if back.is_empty() => 0,
+            None => return Err(format!("bad selector {}", sel)),
+        };
+        return names.len().checked_sub(n + 1)
+            .map(|i| names[i].as_str())
+            .ok_or_else(|| format!("only {} backup{}", names.len(), if names.len() == 1 { "" } else { "s" }));
+    }
+    names.iter().rev()
+        .find(|n| n.starts_with(sel))
+        .map(String::as_str)
+        .ok_or_else(|| format!("no backup matches {}", sel))
+}
+
+// Reads a file's bytes on one side of a diff
+type Reader = Box<dyn Fn(&str) -> Option<Vec<u8>>>;
+
+// A diffable tree: a stored backup (its content fetched from the remote if need be)
+// or, for `device`, the live OP-Z. `wanted` tells which files the reader may be asked for.
+fn load_side(names: &[String], sel: &str, remote: Option<&dyn backend::Backend>, format: Format,
+    wanted: fn(&str) -> bool) -> Result<(String, manifest::Manifest, Reader), String> {
+    if sel == "device" {
+        let mount = opz_mount()?;
+        let manifest = store::scan(Path::new(&mount));
+        let dir = Path::new(&mount).to_path_buf();
+        let reader = move |rel: &str| std::fs::read(dir.join(rel)).ok();
+        return Ok((format!("device ({})", mount), manifest, Box::new(reader)));
+    }
+    let name = select_backup(names, sel)?;
+    let root = backup_root();
+    let snap = Snapshot::load(Path::new(&root), name)?;
+    fetch_for(format, remote, &snap)?;
+    let manifest = snap.manifest.clone();
+    let reader: Reader = match &snap.archive {
+        // Every read would scan the whole archive, so take what may be asked for at once
+        Some(path) => {
+            let files = archive::read_files(path, wanted)?;
+            Box::new(move |rel: &str| files.get(rel).cloned())
+        }
+        None => Box::new(move |rel: &str| snap.read(Path::new(&root), rel).ok()),
+    };
+    Ok((name.to_string(), manifest, reader))
 }
 
-fn load_backups() -> Result<Vec<(String, u64)>, String> {
+fn load_backups() -> Result<Vec<Snapshot>, String> {
     let root = backup_root();
-    let mut entries: Vec<(String, u64)> = std::fs::read_dir(&root)
-        .map_err(|_| format!("no backups in {}", root))?
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
-        .filter_map(|e| {
-            let name = e.file_name().into_string().ok()?;
-            let size = fs_extra::dir::get_size(e.path()).ok()?;
-            Some((name, size))
-        })
-        .collect();
-    entries.sort_by(|a, b| a.0.cmp(&b.0));
-    Ok(entries)
+    backup_names()?
+        .iter()
+        .map(|name| Snapshot::load(Path::new(&root), name))
+        .collect()
 }
 
 fn opz_mount() -> Result<String, String> {
-    let output = Command::new("df").arg("-P").output().map_err(|e| e.to_string())?;
-    let df = String::from_utf8(output.stdout).map_err(|e| e.to_string())?;
-    find_opz(&df)
-        .map(String::from)
-        .ok_or_else(|| "No OP-Z (turn off, press I, turn on, plug USB)".to_string())
+    detect::opz_mount(&Config::load()?.device_name)
 }
 
 // Returns relative-path → size for every file under root (iterative, no recursion)
@@ -129,64 +409,289 @@ fn walk_dir(root: &Path) -> BTreeMap<String, u64> {
     map
 }
 
-fn run() -> Result<u64, String> {
+// Newest snapshot a backup can build on: has hashes, passed verification, covers the
+// whole device (not a single project save)
+fn latest_snapshot(root: &Path) -> Option<Snapshot> {
+    backup_names().ok()?
+        .iter()
+        .rev()
+        .filter_map(|name| Snapshot::load(root, name).ok())
+        .find(|s| s.plain.is_none() && !s.manifest.failed && s.manifest.tag.as_deref() != Some(PROJECT_TAG))
+}
+
+// With `[encryption] enabled`, the first backup into a fresh root sets it up
+fn init_encryption(root: &str, config: &Config) -> Result<(), String> {
+    if config.encryption.enabled && !crypto::is_encrypted(Path::new(root)) {
+        println!("→ setting up encryption for {}", root);
+        crypto::init(Path::new(root), &crypto::secret(true)?, config.encryption.plain_metadata)?;
+    }
+    Ok(())
+}
+
+// Sends a new snapshot to the remote, if there is one
+fn upload(root: &str, name: &str) -> Result<(), String> {
+    if let Some(remote) = remote()? {
+        println!("→ {}", remote.describe());
+        let sent = backend::push(Path::new(root), name, remote.as_ref())
+            .map_err(|e| format!("{} (the backup is kept in {}; retry with `opz-backup push`)", e, root))?;
+        println!("✓ {} uploaded", hb(sent));
+    }
+    Ok(())
+}
+
+struct BackupOptions {
+    verify: bool,
+    incremental: bool,
+    force: bool,
+    archive: Option<archive::Kind>,
+}
+
+// Returns the bytes copied, or None when the OP-Z was unchanged and nothing was stored
+fn run(opts: BackupOptions) -> Result<Option<u64>, String> {
     let src = opz_mount()?;
-    let dst = format!("{}/{}", backup_root(), chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"));
-    println!("→ {}", dst);
-    std::fs::create_dir_all(&dst).map_err(|e| e.to_string())?;
-    backup_copy(&src, &dst, false)
+    let root = backup_root();
+    let config = Config::load()?;
+    init_encryption(&root, &config)?;
+    if opts.archive.is_some() && crypto::is_encrypted(Path::new(&root)) {
+        return Err(format!("{} is encrypted; archives would be written in the clear", root));
+    }
+    let mut latest = latest_snapshot(Path::new(&root));
+
+    // Same content as the latest backup: just note that we looked
+    if let Some(latest) = latest.as_mut().filter(|_| !opts.force)
+        && device_matches(latest, Path::new(&src))? {
+        std::fs::create_dir_all(&root).map_err(|e| e.to_string())?;
+        store::record_check(Path::new(&root), &latest.name)?;
+        println!("✓ OP-Z unchanged since {} — nothing to back up", latest.name);
+        return Ok(None);
+    }
+
+    let name = chrono::Local::now().format(store::NAME_FORMAT).to_string();
+    println!("→ {}/{}", root, name);
+    let bytes = match opts.archive {
+        Some(kind) => archive::snapshot(Path::new(&root), Path::new(&src), &name, kind)?,
+        None => {
+            let base = match latest {
+                Some(snap) if opts.incremental => snap.manifest,
+                _ => manifest::Manifest::default(),
+            };
+            store::store_incremental(Path::new(&root), Path::new(&src), &name, &base)?
+        }
+    };
+    if opts.verify {
+        verify_backup(Path::new(&root), &name, Path::new(&src))?;
+    }
+    upload(&root, &name)?;
+    let retention = config.retention;
+    if retention.auto && !retention.is_empty() {
+        prune_backups(retention, false)?;
+    }
+    Ok(Some(bytes))
+}
+
+// Whether the device holds exactly what the snapshot does. Files whose size matches are
+// hashed, since a same-size edit may keep its mtime.
+fn device_matches(snap: &mut Snapshot, src: &Path) -> Result<bool, String> {
+    let mut device = store::scan(src);
+    store::hash_same_size(snap, src, &mut device)?;
+    Ok(diff::compare(&snap.manifest.files, &device.files).is_empty())
+}
+
+// Re-reads both sides of a fresh backup; a USB hiccup mid-copy shows up here
+// rather than months later at restore time. Marks the snapshot failed on mismatch.
+fn verify_backup(root: &Path, name: &str, src: &Path) -> Result<(), String> {
+    println!("→ verifying against {}", src.display());
+    let mut snap = Snapshot::load(root, name)?;
+    let report = store::verify(root, &snap)?;
+    let mut bad = store::compare_with(root, &snap, src)?;
+    bad.extend(report.missing);
+    bad.extend(report.corrupted);
+    bad.sort();
+    bad.dedup();
+
+    if bad.is_empty() {
+        return Ok(());
+    }
+    for path in &bad {
+        println!("  ! {}", path);
+    }
+    snap.manifest.failed = true;
+    snap.save_manifest(root)?;
+    Err(format!("{} failed verification ({} file{} differ)",
+        name, bad.len(), if bad.len() == 1 { "" } else { "s" }))
 }
 
-fn list_backups() -> Result<(), String> {
+fn list_backups(format: Format, detail: bool, pack: Option<&str>) -> Result<(), String> {
+    let remote = remote_for(format);
     let root = backup_root();
-    let entries = load_backups()?;
+
+    // With --detail or --pack, each backup's drum packs (only those matching --pack)
+    let query = pack.map(str::to_lowercase);
+    let mut entries: Vec<(Snapshot, Vec<PackRecord>)> = Vec::new();
+    for snap in load_backups()? {
+        let mut packs = Vec::new();
+        if detail || query.is_some() {
+            fetch_for(format, remote.as_deref(), &snap)?;
+            packs = aiff::packs(Path::new(&root), &snap).iter()
+                .filter(|(_, p)| query.as_ref().is_none_or(|q| p.name.to_lowercase().contains(q)))
+                .map(|(path, p)| PackRecord::new(path, p))
+                .collect();
+        }
+        entries.push((snap, packs));
+    }
+    if query.is_some() {
+        entries.retain(|(_, packs)| !packs.is_empty());
+    }
+
+    match format {
+        Format::Json => return output::print_json(&entries.into_iter()
+            .map(|(snap, packs)| BackupRecord { packs, ..BackupRecord::new(&snap) })
+            .collect::<Vec<_>>()),
+        Format::Tsv => {
+            println!("{}", BackupRecord::TSV_HEADER);
+            entries.iter().for_each(|(s, _)| println!("{}", BackupRecord::new(s).tsv()));
+            return Ok(());
+        }
+        Format::Text => {}
+    }
 
     if entries.is_empty() {
-        println!("no backups in {}", root);
+        match pack {
+            Some(pack) => println!("no backup holds a drum pack matching \"{}\"", pack),
+            None => println!("no backups in {}", root),
+        }
         return Ok(());
     }
 
-    let total: u64 = entries.iter().map(|(_, s)| s).sum();
+    let total: u64 = entries.iter().map(|(s, _)| s.manifest.total_size()).sum();
     let n = entries.len();
     println!("root   {}", root);
-    println!("total  {}  ({} backup{})\n", hb(total), n, if n == 1 { "" } else { "s" });
+    println!("total  {}  ({} backup{}, {} stored)\n",
+        hb(total), n, if n == 1 { "" } else { "s" }, hb(store::stored_size(Path::new(&root))));
+
+    for (snap, packs) in &entries {
+        let failed = if snap.manifest.failed { "   ✗ failed verification" } else { "" };
+        let tag = match (&snap.manifest.tag, &snap.manifest.restoring) {
+            (Some(tag), Some(of)) => format!("   {} of {}", tag, of),
+            (Some(tag), None) => format!("   {}", tag),
+            _ => String::new(),
+        } + &snap.manifest.label.as_ref().map(|l| format!(" \"{}\"", l)).unwrap_or_default();
+        let packed = match &snap.archive {
+            Some(path) => format!("   {} archive", hb(snap.own_size()))
+                + &archive::Kind::of(path).map(|k| format!(" ({})", k.extension())).unwrap_or_default(),
+            None => String::new(),
+        };
+        println!("  {}   {}{}{}{}", snap.name, hb(snap.manifest.total_size()), packed, tag, failed);
+        for p in packs {
+            println!("      {:<14} {:<20} {:>2} slice{}  {:.1}s",
+                p.slot, p.name, p.slices, if p.slices == 1 { " " } else { "s" }, p.seconds);
+        }
+    }
+
+    Ok(())
+}
 
-    for (name, size) in &entries {
-        println!("  {}   {}", name, hb(*size));
+// Packs a stored backup into one file; an already archived one in the same format is copied as is
+fn export_backup(sel: &str, file: &Path, decrypt: bool) -> Result<(), String> {
+    let kind = archive::Kind::of(file)
+        .ok_or_else(|| format!("{}: use a .tar.zst or .zip file name", file.display()))?;
+    let root = backup_root();
+    if !decrypt && crypto::is_encrypted(Path::new(&root)) {
+        return Err(format!("{} is encrypted; the archive would be written in the clear (pass --decrypt)", root));
     }
+    let remote = pull_remote();
+    let names = backup_names()?;
+    let snap = Snapshot::load(Path::new(&root), select_backup(&names, sel)?)?;
+    fetch_remote(remote.as_deref(), &snap)?;
+    println!("→ {} → {}", snap.name, file.display());
+
+    let bytes = match &snap.archive {
+        Some(path) if archive::Kind::of(path) == Some(kind) => {
+            std::fs::copy(path, file).map_err(|e| format!("{}: {}", file.display(), e))?
+        }
+        // The other format: read the stored archive in one pass and pack it again
+        Some(path) => {
+            let files = archive::read_files(path, |_| true)?;
+            archive::write(file, kind, &snap.manifest, |rel| {
+                let data = files.get(rel).ok_or_else(|| format!("{}: no {}", path.display(), rel))?;
+                Ok(Box::new(data.as_slice()) as Box<dyn std::io::Read>)
+            })?
+        }
+        None => archive::write(file, kind, &snap.manifest, |rel| snap.open(Path::new(&root), rel))?,
+    };
+    println!("✓ {} files, {} archive", snap.manifest.files.len(), hb(bytes));
+    Ok(())
+}
 
+fn import_backup(path: &Path, date: Option<&str>) -> Result<(), String> {
+    let root = backup_root();
+    println!("→ importing {}", path.display());
+    let (name, bytes) = import::import(Path::new(&root), path, date)?;
+    println!("✓ {}/{}   ({} new)", root, name, hb(bytes));
     Ok(())
 }
 
-fn diff_backups() -> Result<(), String> {
+fn diff_backups(a: Option<String>, b: Option<String>, raw: bool, format: Format) -> Result<(), String> {
+    let remote = remote_for(format);
     let names = backup_names()?;
-    if names.len() < 2 {
+    if a.is_none() && names.len() < 2 {
         return Err("need at least 2 backups to diff".to_string());
     }
 
-    let root = backup_root();
-    let a_name = &names[names.len() - 2];
-    let b_name = &names[names.len() - 1];
-    let a = walk_dir(Path::new(&format!("{}/{}", root, a_name)));
-    let b = walk_dir(Path::new(&format!("{}/{}", root, b_name)));
+    // --raw compares manifests only; otherwise packs and settings are read
+    let remote = remote.as_deref().filter(|_| !raw);
+    let wanted: fn(&str) -> bool = match raw {
+        true => |_| false,
+        false => |rel| rel.starts_with("config/") || aiff::is_pack(rel),
+    };
+    let (a_name, a, a_read) = load_side(&names, a.as_deref().unwrap_or("latest~1"), remote, format, wanted)?;
+    let (b_name, b, b_read) = load_side(&names, b.as_deref().unwrap_or("latest"), remote, format, wanted)?;
+    let changes = diff::compare(&a.files, &b.files);
+    let mut summary = if raw { Vec::new() } else { diff::summarize(&a.files, &b.files) };
+    diff::describe_packs(&mut summary, &a.files, &b.files,
+        |rel| aiff::parse(&a_read(rel)?).ok(), |rel| aiff::parse(&b_read(rel)?).ok());
+    diff::describe_settings(&mut summary, &a_read, &b_read);
+
+    let record = DiffRecord { from: a_name, to: b_name, changes, summary };
+    match format {
+        Format::Json => return output::print_json(&record),
+        Format::Tsv => {
+            println!("{}", DiffRecord::TSV_HEADER);
+            record.tsv_rows().iter().for_each(|row| println!("{}", row));
+            return Ok(());
+        }
+        Format::Text => {}
+    }
 
-    println!("{} → {}\n", a_name, b_name);
+    println!("{} → {}\n", record.from, record.to);
+
+    if !raw {
+        for change in &record.summary {
+            println!("  {}", change);
+            for setting in &change.settings {
+                println!("      {}", setting);
+            }
+        }
+        match record.summary.len() {
+            0 => println!("  no changes"),
+            n => println!("\n  {} change{}  ({} file{})", n, if n == 1 { "" } else { "s" },
+                record.changes.len(), if record.changes.len() == 1 { "" } else { "s" }),
+        }
+        return Ok(());
+    }
 
     let mut new_count = 0u32;
     let mut del_count = 0u32;
     let mut mod_count = 0u32;
 
-    for (path, &size) in &a {
-        if !b.contains_key(path) {
-            println!("  - {}  {}", path, hb(size));
-            del_count += 1;
-        }
-    }
-    for (path, &size) in &b {
-        match a.get(path) {
-            None => { println!("  + {}  {}", path, hb(size)); new_count += 1; }
-            Some(&old) if old != size => { println!("  ~ {}  {} → {}", path, hb(old), hb(size)); mod_count += 1; }
-            _ => {}
+    for change in record.changes {
+        match change {
+            Change::Removed { path, size } => { println!("  - {}  {}", path, hb(size)); del_count += 1; }
+            Change::Added { path, size } => { println!("  + {}  {}", path, hb(size)); new_count += 1; }
+            Change::Modified { path, old_size, new_size } => {
+                println!("  ~ {}  {} → {}", path, hb(old_size), hb(new_size));
+                mod_count += 1;
+            }
         }
     }
 
@@ -202,7 +707,104 @@ fn diff_backups() -> Result<(), String> {
     Ok(())
 }
 
-fn status() -> Result<(), String> {
+fn verify(backup: Option<String>, all: bool) -> Result<(), String> {
+    let remote = pull_remote();
+    let names = backup_names()?;
+    let targets = match (backup, all) {
+        (_, true) => names,
+        (Some(sel), false) => vec![select_backup(&names, &sel)?.to_string()],
+        (None, false) => names.last().cloned().into_iter().collect(),
+    };
+    if targets.is_empty() {
+        return Err(format!("no backups in {}", backup_root()));
+    }
+
+    let root = backup_root();
+    let mut bad = 0usize;
+    for name in &targets {
+        let snap = Snapshot::load(Path::new(&root), name)?;
+        if snap.plain.is_some() {
+            println!("  {}   skipped (plain copy, no manifest)", name);
+            continue;
+        }
+        println!("{}", name);
+        fetch_remote(remote.as_deref(), &snap)?;
+        let report = store::verify(Path::new(&root), &snap)?;
+        for path in &report.missing { println!("  ! missing    {}", path); }
+        for path in &report.corrupted { println!("  ! corrupted  {}", path); }
+        for path in &report.extra { println!("  ? extra      {}", path); }
+        if report.is_clean() {
+            println!("  ✓ {} files ok", snap.manifest.files.len());
+        } else {
+            bad += 1;
+        }
+    }
+
+    match bad {
+        0 => Ok(()),
+        n => Err(format!("{} backup{} failed verification", n, if n == 1 { "" } else { "s" })),
+    }
+}
+
+fn prune_backups(policy: prune::Policy, dry_run: bool) -> Result<(), String> {
+    let policy = if policy.is_empty() { Config::load()?.retention } else { policy };
+    if policy.is_empty() {
+        return Err("no retention rules (pass --keep-last/--keep-daily/... or set [retention] in the config)".to_string());
+    }
+
+    let root = backup_root();
+    // Pruning the remote blind could drop backups only it has, so a failed pull skips it
+    let remote = remote().map_err(|e| format!("remote: {}", e))
+        .and_then(|remote| remote.map(|r| pull_from(r.as_ref()).map(|()| r)).transpose());
+    if let Err(e) = &remote {
+        eprintln!("! {}", e);
+    }
+    let plan = prune::plan(Path::new(&root), &backup_names()?, &policy)?;
+    if plan.remove.is_empty() {
+        println!("nothing to prune ({} backup{} kept)", plan.keep.len(), if plan.keep.len() == 1 { "" } else { "s" });
+        return Ok(());
+    }
+
+    for name in &plan.remove {
+        println!("  - {}", name);
+    }
+    let n = plan.remove.len();
+    if dry_run {
+        println!("\n  would remove {} backup{}, freeing {}", n, if n == 1 { "" } else { "s" }, hb(plan.freed));
+        return Ok(());
+    }
+    prune::apply(Path::new(&root), &plan)?;
+    println!("✓ removed {} backup{}, freed {}", n, if n == 1 { "" } else { "s" }, hb(plan.freed));
+    match remote {
+        Ok(Some(remote)) => {
+            let freed = backend::prune(Path::new(&root), &plan, remote.as_ref())?;
+            println!("✓ removed them from {}, freed {}", remote.describe(), hb(freed));
+        }
+        Ok(None) => {}
+        Err(e) => return Err(format!("{}; the remote was not pruned", e)),
+    }
+    Ok(())
+}
+
+fn status(format: Format) -> Result<(), String> {
+    if format != Format::Text {
+        let entries = load_backups().unwrap_or_default();
+        let mount = opz_mount().ok();
+        let record = StatusRecord {
+            connected: mount.is_some(),
+            mount,
+            root: backup_root(),
+            backups: entries.len(),
+            total_bytes: entries.iter().map(|s| s.manifest.total_size()).sum(),
+            last_backup: entries.last().map(BackupRecord::new),
+            last_check: store::last_check(Path::new(&backup_root())),
+        };
+        return match format {
+            Format::Json => output::print_json(&record),
+            _ => { println!("{}", record.tsv()); Ok(()) }
+        };
+    }
+
     match opz_mount() {
         Ok(path) => println!("OP-Z    connected   {}", path),
         Err(_)   => println!("OP-Z    not connected"),
@@ -215,17 +817,21 @@ fn status() -> Result<(), String> {
         println!("root    {}", root);
     } else {
         let last = entries.last().unwrap();
-        let total: u64 = entries.iter().map(|(_, s)| s).sum();
+        let total: u64 = entries.iter().map(|s| s.manifest.total_size()).sum();
         let n = entries.len();
         println!("backup  {} backup{}   last: {}  ({})",
-            n, if n == 1 { "" } else { "s" }, last.0, hb(last.1));
+            n, if n == 1 { "" } else { "s" }, last.name, hb(last.manifest.total_size()));
         println!("root    {}   ({} total)", root, hb(total));
     }
+    if let Some(check) = store::last_check(Path::new(&root)) {
+        println!("checked {}   unchanged since {}", check.time.replace('T', " "), check.snapshot);
+    }
 
     Ok(())
 }
 
 fn open_backup() -> Result<(), String> {
+    let remote = pull_remote();
     let names = backup_names()?;
     if names.is_empty() {
         return Err(format!("no backups in {}", backup_root()));
@@ -239,13 +845,203 @@ fn open_backup() -> Result<(), String> {
         .interact()
         .map_err(|e| e.to_string())?;
 
-    let path = format!("{}/{}", backup_root(), names[idx]);
+    let root = backup_root();
+    let snap = Snapshot::load(Path::new(&root), &names[idx])?;
+    let path = match &snap.plain {
+        Some(dir) => dir.clone(),
+        None => {
+            // Materialize on demand into a scratch folder the file manager can browse
+            let dir = std::env::temp_dir().join("opz-backup").join(&snap.name);
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+            }
+            fetch_remote(remote.as_deref(), &snap)?;
+            println!("→ {}", dir.display());
+            store::checkout(Path::new(&root), &snap, &dir)?;
+            dir
+        }
+    };
     let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
     Command::new(opener).arg(&path).status().map_err(|e| e.to_string())?;
     Ok(())
 }
 
-fn restore() -> Result<(), String> {
+// Interactive tree picker over the snapshot's folders and files
+fn select_paths(theme: &dialoguer::theme::ColorfulTheme, snap: &Snapshot) -> Result<Vec<String>, String> {
+    let items = store::tree_items(&snap.manifest.files, 3);
+    let labels: Vec<&str> = items.iter().map(|(_, label)| label.as_str()).collect();
+    let picked = dialoguer::MultiSelect::with_theme(theme)
+        .with_prompt("Select what to restore (space to toggle, enter to confirm)")
+        .items(&labels)
+        .interact()
+        .map_err(|e| e.to_string())?;
+    Ok(picked.into_iter().map(|i| items[i].0.clone()).collect())
+}
+
+fn print_plan(plan: &plan::Plan) {
+    let files = |n: usize| format!("{} file{}", n, if n == 1 { "" } else { "s" });
+    for (path, old, new) in &plan.overwrite {
+        println!("  ~ {}  {} → {}", path, hb(*old), hb(*new));
+    }
+    for (path, size) in &plan.add {
+        println!("  + {}  {}", path, hb(*size));
+    }
+    for (path, size) in &plan.delete {
+        println!("  - {}  {}  (only on OP-Z, deleted)", path, hb(*size));
+    }
+    for (path, size) in &plan.device_only {
+        println!("  · {}  {}  (only on OP-Z, left in place)", path, hb(*size));
+    }
+    println!();
+    println!("  overwrite    {:<10} {}", files(plan.overwrite.len()), hb(plan.overwrite_bytes()));
+    println!("  add          {:<10} {}", files(plan.add.len()), hb(plan.add_bytes()));
+    if !plan.delete.is_empty() {
+        println!("  delete       {:<10} {}", files(plan.delete.len()), hb(plan.delete_bytes()));
+    }
+    println!("  untouched    {:<10} {}", files(plan.untouched.0), hb(plan.untouched.1));
+    println!("  only on OP-Z {:<10} {}", files(plan.device_only.len()), hb(plan.device_only_bytes()));
+}
+
+// Snapshots the device as it is right now, so a restore to the wrong backup can be undone
+fn safety_snapshot(root: &Path, device: &Path, restoring: &str) -> Result<String, String> {
+    let name = format!("{}-{}", chrono::Local::now().format(store::NAME_FORMAT), PRE_RESTORE);
+    println!("→ saving current OP-Z state as {}", name);
+    store::store_snapshot(root, device, &name)?;
+
+    let mut snap = Snapshot::load(root, &name)?;
+    snap.manifest.tag = Some(PRE_RESTORE.to_string());
+    snap.manifest.restoring = Some(restoring.to_string());
+    snap.save_manifest(root)?;
+    Ok(name)
+}
+
+// Snapshots one project slot of the device. `restoring` marks the safety copy taken
+// before a project restore overwrites the slot.
+fn save_project(root: &Path, device: &Path, slot: u8, label: Option<String>, restoring: Option<&str>) -> Result<String, String> {
+    let name = format!("{}-project{:02}", chrono::Local::now().format(store::NAME_FORMAT), slot);
+    store::store_paths(root, device, &name, &[content::Item::Project { slot }.path()])
+        .map_err(|_| format!("project {} is empty on the OP-Z", slot))?;
+
+    let mut snap = Snapshot::load(root, &name)?;
+    snap.manifest.tag = Some(PROJECT_TAG.to_string());
+    snap.manifest.label = label;
+    snap.manifest.restoring = restoring.map(str::to_string);
+    snap.save_manifest(root)?;
+    Ok(name)
+}
+
+fn project_save(slot: u8, label: Option<String>) -> Result<(), String> {
+    let src = opz_mount()?;
+    let root = backup_root();
+    init_encryption(&root, &Config::load()?)?;
+    let name = save_project(Path::new(&root), Path::new(&src), slot, label, None)?;
+    println!("✓ project {} saved as {}/{}", slot, root, name);
+    upload(&root, &name)
+}
+
+fn project_restore(slot: u8, from: Option<String>, to_slot: Option<u8>, dry_run: bool) -> Result<(), String> {
+    let remote = pull_remote();
+    let root = backup_root();
+    let names = backup_names()?;
+    let rel = content::Item::Project { slot }.path();
+    let to = to_slot.unwrap_or(slot);
+    let to_rel = content::Item::Project { slot: to }.path();
+
+    // Safety copies taken by earlier project restores aren't what "last saved" means
+    let snap = match &from {
+        Some(sel) => Snapshot::load(Path::new(&root), select_backup(&names, sel)?)?,
+        None => names.iter().rev()
+            .filter_map(|name| Snapshot::load(Path::new(&root), name).ok())
+            .find(|s| s.manifest.restoring.is_none() && !s.manifest.failed && s.manifest.files.contains_key(&rel))
+            .ok_or_else(|| format!("no backup holds project {}", slot))?,
+    };
+    let entry = snap.manifest.files.get(&rel).cloned()
+        .ok_or_else(|| format!("{} has no project {}", snap.name, slot))?;
+
+    let dst = opz_mount()?;
+    if !Path::new(&dst).join("projects").is_dir() {
+        return Err(format!("{} has no projects folder; is it an OP-Z?", dst));
+    }
+    let target = Path::new(&dst).join(&to_rel);
+    let current = match target.is_file() {
+        true => Some((store::hash_file(&target)?, std::fs::metadata(&target).map_err(|e| e.to_string())?.len())),
+        false => None,
+    };
+
+    // Plain copies record no hashes (and never leave the local disk): hash the copy
+    let want = match &entry.hash {
+        Some(hash) => hash.clone(),
+        None => store::hash_reader(&snap.read(Path::new(&root), &rel)?[..])?,
+    };
+
+    println!("project {} from {} → project {} on {}\n", slot, snap.name, to, dst);
+    match &current {
+        Some((hash, _)) if *hash == want => {
+            println!("✓ project {} already matches", to);
+            return Ok(());
+        }
+        Some((_, size)) => println!("  ~ {}  {} → {}\n", to_rel, hb(*size), hb(entry.size)),
+        None => println!("  + {}  {}\n", to_rel, hb(entry.size)),
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let theme = dialoguer::theme::ColorfulTheme::default();
+    let confirmed = dialoguer::Confirm::with_theme(&theme)
+        .with_prompt(format!("Restore project {} from {} → project {} on {}?", slot, snap.name, to, dst))
+        .default(false)
+        .interact()
+        .map_err(|e| e.to_string())?;
+    if !confirmed {
+        println!("cancelled");
+        return Ok(());
+    }
+
+    // Fetched first: a backup that can't be fetched leaves no safety copy behind
+    fetch_remote(remote.as_deref(), &snap)?;
+    if current.is_some() {
+        let safety = save_project(Path::new(&root), Path::new(&dst), to, None, Some(&snap.name))?;
+        println!("  (undo with `opz-backup project restore {} --from {}`)", to, safety);
+    }
+
+    // Whole file or nothing: a half-written project is worse than the old one. The project
+    // is checked out beside the slots on the OP-Z, then moved into place.
+    let scratch = Path::new(&dst).join(format!(".opz-restore-{}", std::process::id()));
+    let bytes = store::checkout_only(Path::new(&root), &snap, &scratch, |r| r == rel)
+        .and_then(|bytes| std::fs::rename(scratch.join(&rel), &target).map(|()| bytes)
+            .map_err(|e| format!("{}: {}", target.display(), e)));
+    std::fs::remove_dir_all(&scratch).ok();
+    let bytes = bytes?;
+
+    println!("→ verifying {}", to_rel);
+    if store::hash_file(&target)? != want {
+        return Err(format!("restore incomplete: {} differs from {}", to_rel, snap.name));
+    }
+    println!("✓ project {} restored ({})", to, hb(bytes));
+    Ok(())
+}
+
+fn undo_restore(dry_run: bool) -> Result<(), String> {
+    let last = load_backups()?
+        .into_iter()
+        .rev()
+        .find(|s| s.manifest.tag.as_deref() == Some(PRE_RESTORE))
+        .ok_or("no pre-restore snapshot to go back to")?;
+    // Mirror, so files the restore added disappear again. This takes its own
+    // pre-restore snapshot, so running undo-restore twice redoes the restore.
+    restore(RestoreArgs {
+        backup: Some(last.name),
+        only: Vec::new(),
+        select: false,
+        no_verify: false,
+        dry_run,
+        mirror: true,
+    })
+}
+
+fn restore(args: RestoreArgs) -> Result<(), String> {
+    let remote = pull_remote();
     let names = backup_names()?;
     if names.is_empty() {
         return Err(format!("no backups in {}", backup_root()));
@@ -253,19 +1049,77 @@ fn restore() -> Result<(), String> {
 
     let theme = dialoguer::theme::ColorfulTheme::default();
 
-    let idx = dialoguer::Select::with_theme(&theme)
-        .with_prompt("Select backup to restore")
-        .items(&names)
-        .default(names.len() - 1)
-        .interact()
-        .map_err(|e| e.to_string())?;
+    let name = match &args.backup {
+        Some(sel) => select_backup(&names, sel)?,
+        None if args.dry_run => select_backup(&names, "latest")?,
+        None => {
+            let idx = dialoguer::Select::with_theme(&theme)
+                .with_prompt("Select backup to restore")
+                .items(&names)
+                .default(names.len() - 1)
+                .interact()
+                .map_err(|e| e.to_string())?;
+            &names[idx]
+        }
+    };
+    let root = backup_root();
+    let mut snap = Snapshot::load(Path::new(&root), name)?;
 
-    let name = &names[idx];
-    let src = format!("{}/{}", backup_root(), name);
+    let only = if args.select {
+        select_paths(&theme, &snap)?
+    } else {
+        args.only.iter().map(|sel| content::selector(sel)).collect()
+    };
+    let partial = !only.is_empty();
+    if args.select && !partial {
+        println!("nothing selected");
+        return Ok(());
+    }
+    if partial {
+        snap.retain_paths(&only)?;
+    }
+    if args.mirror && snap.manifest.tag.as_deref() == Some(PROJECT_TAG) {
+        return Err(format!("{} holds a single project; mirroring it would delete everything else", name));
+    }
     let dst = opz_mount()?;
+    if args.mirror && !detect::has_opz_layout(Path::new(&dst)) {
+        return Err(format!("refusing to mirror: {} doesn't look like an OP-Z (expected folders: {})",
+            dst, detect::LAYOUT.join(", ")));
+    }
 
+    let mut device = store::scan(Path::new(&dst));
+    if partial {
+        device.files.retain(|rel, _| only.iter().any(|sel| store::path_matches(rel, sel)));
+    }
+    store::hash_same_size(&mut snap, Path::new(&dst), &mut device)?;
+    let plan = plan::restore_plan(&device, &snap.manifest, args.mirror);
+    println!("{} → {}\n", name, dst);
+    print_plan(&plan);
+    println!();
+    if args.dry_run {
+        return Ok(());
+    }
+    if plan.is_noop() {
+        println!("✓ OP-Z already matches {}", name);
+        return Ok(());
+    }
+
+    let what = if partial {
+        // Name a few slots ("project 3, kick slot 7"); count files beyond that
+        let items: Vec<String> = content::items(&snap.manifest.files).keys().map(|i| i.to_string()).collect();
+        match items.len() {
+            1..=3 => format!("{} from {}", items.join(", "), name),
+            _ => format!("{} file{} from {}", snap.manifest.files.len(), if snap.manifest.files.len() == 1 { "" } else { "s" }, name),
+        }
+    } else {
+        name.to_string()
+    };
+    let deleting = match plan.delete.len() {
+        0 => String::new(),
+        n => format!(" and delete {} file{} from the OP-Z", n, if n == 1 { "" } else { "s" }),
+    };
     let confirmed = dialoguer::Confirm::with_theme(&theme)
-        .with_prompt(format!("Restore {} → {}?", name, dst))
+        .with_prompt(format!("Restore {} → {}{}?", what, dst, deleting))
         .default(false)
         .interact()
         .map_err(|e| e.to_string())?;
@@ -275,12 +1129,103 @@ fn restore() -> Result<(), String> {
         return Ok(());
     }
 
-    println!("→ restoring {} to {}", name, dst);
-    let bytes = backup_copy(&src, &dst, true)?;
+    fetch_remote(remote.as_deref(), &snap)?;
+    let safety = safety_snapshot(Path::new(&root), Path::new(&dst), name)?;
+    println!("  (undo with `opz-backup undo-restore`, which restores {})", safety);
+
+    println!("→ restoring {} to {}", what, dst);
+    // Only what the plan overwrites or adds is written; untouched files stay as they are
+    let writes = plan.writes();
+    let bytes = match &snap.plain {
+        Some(dir) if !partial && plan.untouched.0 == 0 => backup_copy(dir.to_str().ok_or("non-UTF-8 backup path")?, &dst, true)?,
+        _ => store::checkout_only(Path::new(&root), &snap, Path::new(&dst), |rel| writes.contains(rel))?,
+    };
+    if !plan.delete.is_empty() {
+        plan.delete_from(Path::new(&dst))?;
+        println!("✓ {} file{} deleted", plan.delete.len(), if plan.delete.len() == 1 { "" } else { "s" });
+    }
+    if !args.no_verify {
+        println!("→ verifying {}", dst);
+        let bad = store::compare_with(Path::new(&root), &snap, Path::new(&dst))?;
+        if !bad.is_empty() {
+            for path in &bad {
+                println!("  ! {}", path);
+            }
+            return Err(format!("restore incomplete: {} file{} differ from {}",
+                bad.len(), if bad.len() == 1 { "" } else { "s" }, name));
+        }
+    }
     println!("✓ {} restored", hb(bytes));
     Ok(())
 }
 
+fn show_config(sel: Option<&str>, format: Format) -> Result<(), String> {
+    let (names, remote) = match sel {
+        Some("device") => (Vec::new(), None),
+        _ => {
+            let remote = remote_for(format);
+            (backup_names()?, remote)
+        }
+    };
+    let (name, manifest, read) = load_side(&names, sel.unwrap_or("latest"), remote.as_deref(), format,
+        |rel| rel.starts_with("config/"))?;
+    let files: Vec<&String> = manifest.files.keys().filter(|rel| rel.starts_with("config/")).collect();
+    if files.is_empty() {
+        return Err(format!("{} has no config files", name));
+    }
+
+    let mut all = BTreeMap::new();
+    let mut text = Vec::new();
+    for rel in files {
+        let parsed = read(rel).ok_or_else(|| format!("{}: can't read {}", name, rel))
+            .and_then(|bytes| settings::flatten(rel, &bytes));
+        match parsed {
+            Ok(flat) => {
+                let width = flat.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+                text.extend(flat.iter().map(|(k, v)| format!("  {:<width$}  {}", k, settings::show(v))));
+                text.push(String::new());
+                all.extend(flat);
+            }
+            Err(_) => text.extend([format!("  {}  (not JSON)", rel), String::new()]),
+        }
+    }
+
+    match format {
+        Format::Json => output::print_json(&all),
+        Format::Tsv => {
+            println!("key\tvalue");
+            all.iter().for_each(|(k, v)| println!("{}\t{}", k, settings::show(v)));
+            Ok(())
+        }
+        Format::Text => {
+            println!("{}\n", name);
+            text.pop();
+            text.iter().for_each(|line| println!("{}", line));
+            Ok(())
+        }
+    }
+}
+
+// Uploads every stored backup the remote is missing, oldest first
+fn push_backups() -> Result<(), String> {
+    let remote = remote()?.ok_or("no remote (pass --remote or set `remote` in the config)")?;
+    let root = backup_root();
+    let there: std::collections::HashSet<String> = backend::names(remote.as_ref())?.into_iter().collect();
+    let mut pushed = 0usize;
+    for snap in load_backups()?.iter().filter(|s| !there.contains(&s.name)) {
+        if snap.plain.is_some() {
+            println!("  {}   skipped (plain copy, no manifest)", snap.name);
+            continue;
+        }
+        println!("→ {}", snap.name);
+        let sent = backend::push(Path::new(&root), &snap.name, remote.as_ref())?;
+        println!("  ✓ {} uploaded", hb(sent));
+        pushed += 1;
+    }
+    println!("{} is up to date ({} backup{} uploaded)", remote.describe(), pushed, if pushed == 1 { "" } else { "s" });
+    Ok(())
+}
+
 fn watch() -> Result<(), String> {
     // Initialise to current state so we only trigger on new plug-ins
     let mut was_connected = opz_mount().is_ok();
@@ -296,9 +1241,12 @@ fn watch() -> Result<(), String> {
 
         if connected && !was_connected {
             println!("OP-Z connected — backing up...");
-            match run() {
-                Ok(b)  => println!("✓ {} copied — watching...", hb(b)),
-                Err(e) => eprintln!("✗ {} — watching...", e),
+            let config = Config::load()?;
+            let opts = BackupOptions { verify: config.verify, incremental: config.incremental, force: false, archive: None };
+            match run(opts) {
+                Ok(Some(b)) => println!("✓ {} copied — watching...", hb(b)),
+                Ok(None)    => println!("watching..."),
+                Err(e)      => eprintln!("✗ {} — watching...", e),
             }
         } else if !connected && was_connected {
             println!("OP-Z disconnected — watching...");
@@ -309,17 +1257,45 @@ fn watch() -> Result<(), String> {
 }
 
 fn main() {
-    let result: Result<(), String> = match Cli::parse().command {
-        None                 => run().map(|b| println!("✓ {} copied", hb(b))),
-        Some(Cmd::List)      => list_backups(),
-        Some(Cmd::Restore)   => restore(),
-        Some(Cmd::Diff)      => diff_backups(),
-        Some(Cmd::Status)    => status(),
+    let cli = Cli::parse();
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("✗ {}", e);
+        std::process::exit(1);
+    });
+    if let Some(root) = cli.root {
+        ROOT_FLAG.set(config::expand_home(&root)).ok();
+    }
+    if let Some(remote) = cli.remote {
+        REMOTE_FLAG.set(config::expand_home(&remote)).ok();
+    }
+
+    let result: Result<(), String> = match cli.command {
+        None => run(BackupOptions {
+            verify: cli.verify || config.verify,
+            incremental: cli.incremental || config.incremental,
+            force: cli.force,
+            archive: cli.archive,
+        }).map(|b| if let Some(b) = b { println!("✓ {} copied", hb(b)) }),
+        Some(Cmd::List { detail, pack }) => list_backups(cli.format, detail, pack.as_deref()),
+        Some(Cmd::Restore(args)) => restore(args),
+        Some(Cmd::UndoRestore { dry_run }) => undo_restore(dry_run),
+        Some(Cmd::Diff { a, b, raw }) => diff_backups(a, b, raw, cli.format),
+        Some(Cmd::Status)    => status(cli.format),
         Some(Cmd::Open)      => open_backup(),
         Some(Cmd::Watch)     => watch(),
+        Some(Cmd::Prune { policy, dry_run }) => prune_backups(policy, dry_run),
+        Some(Cmd::Export { backup, file, decrypt }) => export_backup(&backup, &file, decrypt),
+        Some(Cmd::Import { path, date }) => import_backup(&path, date.as_deref()),
+        Some(Cmd::Push)      => push_backups(),
+        Some(Cmd::Project { action: ProjectCmd::Save { slot, name } }) => project_save(slot, name),
+        Some(Cmd::Project { action: ProjectCmd::Restore { slot, from, to_slot, dry_run } }) =>
+            project_restore(slot, from, to_slot, dry_run),
+        Some(Cmd::Config { action: ConfigCmd::Show { backup } }) => show_config(backup.as_deref(), cli.format),
+        Some(Cmd::Verify { backup, all }) => verify(backup, all),
     };
     if let Err(e) = result {
         eprintln!("✗ {}", e);
+        std::process::exit(1);
     }
 }
 
@@ -332,62 +1308,6 @@ mod tests {
     // Serialize tests that mutate HOME to avoid races with parallel test runner
     static HOME_LOCK: Mutex<()> = Mutex::new(());
 
-    // Realistic df -P output (POSIX: 6 cols, mount point always at index 5)
-    const DF_WITH_OPZ: &str = "\
-Filesystem     1024-blocks      Used  Available Capacity Mounted on
-/dev/disk1s1   244277232   94712304  148564528      39% /
-devfs                396        396          0     100% /dev
-/dev/disk2s1     1953520    1953520          0     100% /Volumes/OP-Z";
-
-    const DF_WITHOUT_OPZ: &str = "\
-Filesystem     1024-blocks      Used  Available Capacity Mounted on
-/dev/disk1s1   244277232   94712304  148564528      39% /
-devfs                396        396          0     100% /dev";
-
-    // ── find_opz ────────────────────────────────────────────────────────────
-
-    #[test]
-    fn find_opz_returns_mount_path() {
-        assert_eq!(find_opz(DF_WITH_OPZ), Some("/Volumes/OP-Z"));
-    }
-
-    #[test]
-    fn find_opz_returns_none_when_not_mounted() {
-        assert_eq!(find_opz(DF_WITHOUT_OPZ), None);
-    }
-
-    #[test]
-    fn find_opz_skips_header_row() {
-        let df = "Filesystem 1 2 3 4 /Volumes/OP-Z\n\
-                  /dev/disk1 100 50 50 50% /normal";
-        assert_eq!(find_opz(df), None);
-    }
-
-    #[test]
-    fn find_opz_matches_on_mount_column_not_filesystem() {
-        let df = "Filesystem     1024-blocks      Used  Available Capacity Mounted on\n\
-                  /dev/OP-Z/s1   244277232   94712304  148564528      39% /other";
-        assert_eq!(find_opz(df), None);
-    }
-
-    #[test]
-    fn find_opz_returns_first_match_when_multiple() {
-        let df = "Filesystem     1024-blocks      Used  Available Capacity Mounted on\n\
-                  /dev/disk2s1     1953520    1953520          0     100% /Volumes/OP-Z\n\
-                  /dev/disk3s1     1953520    1953520          0     100% /Volumes/OP-Z-2";
-        assert_eq!(find_opz(df), Some("/Volumes/OP-Z"));
-    }
-
-    #[test]
-    fn find_opz_empty_input() {
-        assert_eq!(find_opz(""), None);
-    }
-
-    #[test]
-    fn find_opz_header_only() {
-        assert_eq!(find_opz("Filesystem 1024-blocks Used Available Capacity Mounted on"), None);
-    }
-
     // ── pct ─────────────────────────────────────────────────────────────────
 
     #[test]
@@ -537,8 +1457,41 @@ devfs                396        396          0     100% /dev";
         fs::create_dir_all(&b2).unwrap();
 
         let entries = load_backups().unwrap();
-        assert_eq!(entries[0].0, "2026-03-20_10-00-00");
-        assert_eq!(entries[1].0, "2026-03-24_14-30-00");
+        assert_eq!(entries[0].name, "2026-03-20_10-00-00");
+        assert_eq!(entries[1].name, "2026-03-24_14-30-00");
+    }
+
+    #[test]
+    fn load_backups_skips_object_store() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", root.path()) };
+
+        let backups = root.path().join("opz-backups");
+        fs::create_dir_all(backups.join(store::OBJECTS)).unwrap();
+        fs::create_dir_all(backups.join("2026-03-20_10-00-00")).unwrap();
+
+        let entries = load_backups().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "2026-03-20_10-00-00");
+    }
+
+    #[test]
+    fn load_backups_includes_archives() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        let root = backup_root();
+        store::store_snapshot(Path::new(&root), src.path(), "2026-03-20_10-00-00").unwrap();
+        archive::snapshot(Path::new(&root), src.path(), "2026-03-21_10-00-00", archive::Kind::Zip).unwrap();
+
+        let entries = load_backups().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[1].archive.is_some());
+        assert_eq!(entries[1].manifest.files["project01.opz"].size, 4);
+        assert!(list_backups(Format::Text, false, None).is_ok());
     }
 
     #[test]
@@ -561,7 +1514,28 @@ devfs                396        396          0     100% /dev";
         fs::write(b1.join("data.bin"), vec![0u8; 100]).unwrap();
         fs::write(b2.join("data.bin"), vec![0u8; 200]).unwrap();
 
-        assert!(list_backups().is_ok());
+        assert!(list_backups(Format::Text, false, None).is_ok());
+        assert!(list_backups(Format::Json, false, None).is_ok());
+        assert!(list_backups(Format::Tsv, false, None).is_ok());
+    }
+
+    #[test]
+    fn list_backups_detail_and_pack_search() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("samplepacks/1-kick/03")).unwrap();
+        fs::write(src.path().join("samplepacks/1-kick/03/808.aif"), aiff::tests::aiff(aiff::tests::KIT, 10)).unwrap();
+        let root = home.path().join("opz-backups");
+        store::store_snapshot(&root, src.path(), "2026-03-20_10-00-00").unwrap();
+
+        assert!(list_backups(Format::Text, true, None).is_ok());
+        assert!(list_backups(Format::Json, false, Some("808")).is_ok());
+        assert!(list_backups(Format::Text, false, Some("tr-909")).is_ok());
+        let snap = Snapshot::load(&root, "2026-03-20_10-00-00").unwrap();
+        let packs = aiff::packs(&root, &snap);
+        assert_eq!(PackRecord::new("samplepacks/1-kick/03/808.aif", &packs["samplepacks/1-kick/03/808.aif"]).slot, "kick slot 3");
     }
 
     #[test]
@@ -571,7 +1545,7 @@ devfs                396        396          0     100% /dev";
         unsafe { std::env::set_var("HOME", root.path()) };
         fs::create_dir_all(root.path().join("opz-backups")).unwrap();
 
-        assert!(list_backups().is_ok());
+        assert!(list_backups(Format::Text, false, None).is_ok());
     }
 
     // ── diff_backups ─────────────────────────────────────────────────────────
@@ -582,7 +1556,7 @@ devfs                396        396          0     100% /dev";
         let root = tempfile::tempdir().unwrap();
         unsafe { std::env::set_var("HOME", root.path()) };
         fs::create_dir_all(root.path().join("opz-backups").join("2026-03-20_10-00-00")).unwrap();
-        assert!(diff_backups().is_err());
+        assert!(diff_backups(None, None, false, Format::Text).is_err());
     }
 
     #[test]
@@ -599,7 +1573,414 @@ devfs                396        396          0     100% /dev";
         fs::write(b2.join("same.txt"), b"unchanged").unwrap();
         fs::write(b2.join("new.txt"), b"added").unwrap();
 
-        assert!(diff_backups().is_ok());
+        assert!(diff_backups(None, None, false, Format::Text).is_ok());
+        assert!(diff_backups(None, None, true, Format::Text).is_ok());
+        assert!(diff_backups(Some("2026-03-24".into()), Some("latest~1".into()), false, Format::Json).is_ok());
+        assert!(diff_backups(None, None, false, Format::Tsv).is_ok());
+        assert!(diff_backups(Some("2025".into()), None, false, Format::Text).is_err());
+    }
+
+    // ── select_backup ────────────────────────────────────────────────────────
+
+    fn names() -> Vec<String> {
+        ["2026-03-20_10-00-00", "2026-03-24_09-00-00", "2026-03-24_14-30-00"]
+            .iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn select_backup_exact_name() {
+        assert_eq!(select_backup(&names(), "2026-03-20_10-00-00"), Ok("2026-03-20_10-00-00"));
+    }
+
+    #[test]
+    fn select_backup_latest_and_offsets() {
+        assert_eq!(select_backup(&names(), "latest"), Ok("2026-03-24_14-30-00"));
+        assert_eq!(select_backup(&names(), "latest~0"), Ok("2026-03-24_14-30-00"));
+        assert_eq!(select_backup(&names(), "latest~2"), Ok("2026-03-20_10-00-00"));
+        assert!(select_backup(&names(), "latest~3").is_err());
+        assert!(select_backup(&names(), "latest~x").is_err());
+        assert!(select_backup(&names(), "latestish").is_err());
+    }
+
+    #[test]
+    fn select_backup_prefix_picks_newest_match() {
+        assert_eq!(select_backup(&names(), "2026-03-24"), Ok("2026-03-24_14-30-00"));
+        assert_eq!(select_backup(&names(), "2026-03-2"), Ok("2026-03-24_14-30-00"));
+        assert_eq!(select_backup(&names(), "2026-03-20"), Ok("2026-03-20_10-00-00"));
+    }
+
+    #[test]
+    fn select_backup_no_match_errors() {
+        assert!(select_backup(&names(), "2025").is_err());
+        assert!(select_backup(&[], "latest").is_err());
+    }
+
+    // ── verify ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn verify_ok_for_intact_backup() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        store::store_snapshot(Path::new(&backup_root()), src.path(), "2026-03-20_10-00-00").unwrap();
+
+        assert!(verify(None, false).is_ok());
+        assert!(verify(Some("2026-03-20_10-00-00".into()), false).is_ok());
+    }
+
+    #[test]
+    fn verify_errors_when_object_lost() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        let root = backup_root();
+        store::store_snapshot(Path::new(&root), src.path(), "2026-03-20_10-00-00").unwrap();
+        fs::remove_dir_all(Path::new(&root).join(store::OBJECTS)).unwrap();
+
+        assert!(verify(None, true).is_err());
+    }
+
+    #[test]
+    fn verify_backup_marks_snapshot_failed_on_mismatch() {
+        let root = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        store::store_snapshot(root.path(), src.path(), "s").unwrap();
+        assert!(verify_backup(root.path(), "s", src.path()).is_ok());
+
+        // Simulate a cable pop: device content no longer matches what was copied
+        fs::write(src.path().join("project01.opz"), b"da").unwrap();
+        assert!(verify_backup(root.path(), "s", src.path()).is_err());
+        assert!(Snapshot::load(root.path(), "s").unwrap().manifest.failed);
+    }
+
+    #[test]
+    fn verify_backup_marks_archive_failed_on_mismatch() {
+        let root = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        archive::snapshot(root.path(), src.path(), "s", archive::Kind::Zstd).unwrap();
+        assert!(verify_backup(root.path(), "s", src.path()).is_ok());
+
+        fs::write(src.path().join("project01.opz"), b"da").unwrap();
+        assert!(verify_backup(root.path(), "s", src.path()).is_err());
+        assert!(Snapshot::load(root.path(), "s").unwrap().manifest.failed);
+    }
+
+    // ── export_backup ────────────────────────────────────────────────────────
+
+    #[test]
+    fn export_packs_stored_backup() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        store::store_snapshot(Path::new(&backup_root()), src.path(), "2026-03-20_10-00-00").unwrap();
+
+        let out = home.path().join("opz.tar.zst");
+        export_backup("latest", &out, false).unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let manifest = archive::read_manifest(&out).unwrap();
+        archive::extract(&out, &manifest, dst.path()).unwrap();
+        assert_eq!(fs::read(dst.path().join("project01.opz")).unwrap(), b"data");
+
+        assert!(export_backup("latest", &home.path().join("opz.rar"), false).is_err());
+    }
+
+    #[test]
+    fn export_converts_an_archived_backup_to_the_other_format() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        archive::snapshot(Path::new(&backup_root()), src.path(), "2026-03-20_10-00-00", archive::Kind::Zstd).unwrap();
+
+        let out = home.path().join("opz.zip");
+        export_backup("latest", &out, false).unwrap();
+        let manifest = archive::read_manifest(&out).unwrap();
+        assert!(archive::verify(&out, &manifest).unwrap().is_clean());
+        assert_eq!(archive::read_file(&out, "project01.opz").unwrap(), b"data");
+    }
+
+    #[test]
+    fn export_from_encrypted_root_needs_decrypt() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        unsafe { std::env::set_var(crypto::KEY_ENV, "pw") };
+        let root = Path::new(&backup_root()).to_path_buf();
+        crypto::init(&root, b"pw", true).unwrap();
+        fs::write(src.path().join("project01.opz"), b"data").unwrap();
+        store::store_snapshot(&root, src.path(), "2026-03-20_10-00-00").unwrap();
+
+        let out = home.path().join("opz.tar.zst");
+        assert!(export_backup("latest", &out, false).unwrap_err().contains("in the clear"));
+        assert!(!out.exists());
+        export_backup("latest", &out, true).unwrap();
+        unsafe { std::env::remove_var(crypto::KEY_ENV) };
+        let dst = tempfile::tempdir().unwrap();
+        archive::extract(&out, &archive::read_manifest(&out).unwrap(), dst.path()).unwrap();
+        assert_eq!(fs::read(dst.path().join("project01.opz")).unwrap(), b"data");
+    }
+
+    // ── device_matches ───────────────────────────────────────────────────────
+
+    #[test]
+    fn device_matches_sees_same_size_edits() {
+        let root = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        let project = src.path().join("project01.opz");
+        fs::write(&project, b"bars").unwrap();
+        store::store_snapshot(root.path(), src.path(), "2026-03-20_10-00-00").unwrap();
+        let mut snap = Snapshot::load(root.path(), "2026-03-20_10-00-00").unwrap();
+        assert!(device_matches(&mut snap, src.path()).unwrap());
+
+        let mtime = fs::metadata(&project).unwrap().modified().unwrap();
+        fs::write(&project, b"BARS").unwrap();
+        fs::File::options().write(true).open(&project).unwrap().set_modified(mtime).unwrap();
+        assert!(!device_matches(&mut snap, src.path()).unwrap());
+    }
+
+    // ── latest_snapshot ──────────────────────────────────────────────────────
+
+    #[test]
+    fn latest_snapshot_skips_plain_and_failed_snapshots() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let src = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let root = Path::new(&backup_root()).to_path_buf();
+        fs::write(src.path().join("project01.opz"), b"v1").unwrap();
+        store::store_snapshot(&root, src.path(), "2026-03-20_10-00-00").unwrap();
+        fs::write(src.path().join("project01.opz"), b"v2!").unwrap();
+        store::store_snapshot(&root, src.path(), "2026-03-21_10-00-00").unwrap();
+        fs::create_dir_all(root.join("2026-03-22_10-00-00")).unwrap();
+
+        let mut failed = Snapshot::load(&root, "2026-03-21_10-00-00").unwrap().manifest;
+        failed.failed = true;
+        store::save_manifest(&root, "2026-03-21_10-00-00", &failed).unwrap();
+
+        let latest = latest_snapshot(&root).unwrap();
+        assert_eq!(latest.name, "2026-03-20_10-00-00");
+        assert_eq!(latest.manifest.files["project01.opz"].size, 2);
+    }
+
+    // ── safety_snapshot ──────────────────────────────────────────────────────
+
+    #[test]
+    fn safety_snapshot_tags_and_references_restored_backup() {
+        let root = tempfile::tempdir().unwrap();
+        let device = tempfile::tempdir().unwrap();
+        fs::write(device.path().join("project01.opz"), b"current").unwrap();
+
+        let name = safety_snapshot(root.path(), device.path(), "2026-03-20_10-00-00").unwrap();
+        assert!(name.ends_with("-pre-restore"));
+        assert!(store::name_time(&name).is_some());
+
+        let snap = Snapshot::load(root.path(), &name).unwrap();
+        assert_eq!(snap.manifest.tag.as_deref(), Some(PRE_RESTORE));
+        assert_eq!(snap.manifest.restoring.as_deref(), Some("2026-03-20_10-00-00"));
+        assert_eq!(snap.manifest.files.len(), 1);
+    }
+
+    // ── save_project ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn save_project_stores_only_that_slot() {
+        let root = tempfile::tempdir().unwrap();
+        let device = tempfile::tempdir().unwrap();
+        fs::create_dir_all(device.path().join("projects")).unwrap();
+        fs::write(device.path().join("projects/project07.opz"), b"seven").unwrap();
+        fs::write(device.path().join("projects/project08.opz"), b"eight").unwrap();
+
+        let name = save_project(root.path(), device.path(), 7, Some("before jam".into()), None).unwrap();
+        assert!(name.ends_with("-project07"));
+        assert!(store::name_time(&name).is_some());
+        let snap = Snapshot::load(root.path(), &name).unwrap();
+        assert_eq!(snap.manifest.files.keys().collect::<Vec<_>>(), vec!["projects/project07.opz"]);
+        assert_eq!(snap.manifest.tag.as_deref(), Some(PROJECT_TAG));
+        assert_eq!(snap.manifest.label.as_deref(), Some("before jam"));
+        assert_eq!(snap.read(root.path(), "projects/project07.opz").unwrap(), b"seven");
+
+        assert!(save_project(root.path(), device.path(), 3, None, None).unwrap_err().contains("project 3 is empty"));
+    }
+
+    #[test]
+    fn latest_snapshot_skips_project_saves() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let root = Path::new(&backup_root()).to_path_buf();
+        let device = tempfile::tempdir().unwrap();
+        fs::create_dir_all(device.path().join("projects")).unwrap();
+        fs::write(device.path().join("projects/project01.opz"), b"one").unwrap();
+        fs::write(device.path().join("projects/project02.opz"), b"two").unwrap();
+        store::store_snapshot(&root, device.path(), "2026-03-20_10-00-00").unwrap();
+        save_project(&root, device.path(), 1, None, None).unwrap();
+
+        assert_eq!(latest_snapshot(&root).unwrap().name, "2026-03-20_10-00-00");
+    }
+
+    #[test]
+    fn undo_restore_errors_without_pre_restore_snapshot() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        fs::create_dir_all(home.path().join("opz-backups").join("2026-03-20_10-00-00")).unwrap();
+        assert!(undo_restore(true).is_err());
+    }
+
+    // ── prune_backups ────────────────────────────────────────────────────────
+
+    #[test]
+    fn prune_backups_dry_run_keeps_everything() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        for name in ["2026-03-20_10-00-00", "2026-03-24_14-30-00"] {
+            fs::create_dir_all(home.path().join("opz-backups").join(name)).unwrap();
+        }
+
+        let policy = prune::Policy { keep_last: Some(1), ..prune::Policy::default() };
+        prune_backups(policy, true).unwrap();
+        assert_eq!(backup_names().unwrap().len(), 2);
+
+        prune_backups(policy, false).unwrap();
+        assert_eq!(backup_names().unwrap(), vec!["2026-03-24_14-30-00"]);
+    }
+
+    #[test]
+    fn prune_backups_requires_a_policy() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        fs::create_dir_all(home.path().join("opz-backups")).unwrap();
+        assert!(prune_backups(prune::Policy::default(), true).is_err());
+    }
+
+    // ── show_config ──────────────────────────────────────────────────────────
+
+    #[test]
+    fn show_config_prints_settings_of_a_backup() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("config")).unwrap();
+        fs::write(src.path().join("config/midi.json"), br#"{"channel_track_3": 3}"#).unwrap();
+        fs::write(src.path().join("config/blob.bin"), b"\x00").unwrap();
+        let root = home.path().join("opz-backups");
+        store::store_snapshot(&root, src.path(), "2026-03-20_10-00-00").unwrap();
+        archive::snapshot(&root, src.path(), "2026-03-21_10-00-00", archive::Kind::Zstd).unwrap();
+
+        for format in [Format::Text, Format::Json, Format::Tsv] {
+            assert!(show_config(None, format).is_ok());
+            assert!(show_config(Some("2026-03-20"), format).is_ok());
+        }
+        fs::write(src.path().join("config/midi.json"), br#"{"channel_track_3": 10}"#).unwrap();
+        store::store_snapshot(&root, src.path(), "2026-03-22_10-00-00").unwrap();
+        assert!(diff_backups(None, None, false, Format::Json).is_ok());
+        assert!(show_config(Some("2025"), Format::Text).is_err());
+    }
+
+    // ── remote ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn push_then_list_pulls_into_empty_root() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let dir = home.path().join(".config").join("opz-backup");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), "remote = \"~/nas\"\n").unwrap();
+
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("projects")).unwrap();
+        fs::write(src.path().join("projects/project01.opz"), b"bars").unwrap();
+        let root = home.path().join("opz-backups");
+        store::store_snapshot(&root, src.path(), "2026-03-20_10-00-00").unwrap();
+
+        push_backups().unwrap();
+        assert!(home.path().join("nas/2026-03-20_10-00-00").join(manifest::MANIFEST).is_file());
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::create_dir_all(&root).unwrap();
+        list_backups(Format::Text, false, None).unwrap();
+        assert_eq!(backup_names().unwrap(), vec!["2026-03-20_10-00-00"]);
+    }
+
+    #[test]
+    fn commands_reading_content_fetch_remote_only_backups() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let dir = home.path().join(".config").join("opz-backup");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), "remote = \"~/nas\"\n").unwrap();
+
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("config")).unwrap();
+        fs::create_dir_all(src.path().join("samplepacks/1-kick/01")).unwrap();
+        fs::write(src.path().join("config/midi.json"), br#"{"channel_track_3": 3}"#).unwrap();
+        fs::write(src.path().join("samplepacks/1-kick/01/808.aif"), aiff::tests::aiff(aiff::tests::KIT, 10)).unwrap();
+        let root = home.path().join("opz-backups");
+        store::store_snapshot(&root, src.path(), "2026-03-20_10-00-00").unwrap();
+        fs::write(src.path().join("config/midi.json"), br#"{"channel_track_3": 10}"#).unwrap();
+        store::store_snapshot(&root, src.path(), "2026-03-21_10-00-00").unwrap();
+        push_backups().unwrap();
+
+        let objects = |root: &Path| walk_dir(&root.join(store::OBJECTS)).len();
+        let run: [&dyn Fn() -> Result<(), String>; 3] = [
+            &|| show_config(None, Format::Json),
+            &|| list_backups(Format::Json, true, None),
+            &|| diff_backups(None, None, false, Format::Json),
+        ];
+        for command in run {
+            fs::remove_dir_all(&root).unwrap();
+            fs::create_dir_all(&root).unwrap();
+            command().unwrap();
+            assert!(objects(&root) >= 2, "{}", objects(&root));
+        }
+    }
+
+    #[test]
+    fn prune_backups_leaves_the_remote_alone_when_pull_fails() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let dir = home.path().join(".config").join("opz-backup");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), "remote = \"~/nas\"\n").unwrap();
+        let src = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("project01.opz"), b"bars").unwrap();
+        for name in ["2026-03-20_10-00-00", "2026-03-24_14-30-00"] {
+            store::store_snapshot(&home.path().join("opz-backups"), src.path(), name).unwrap();
+        }
+        // An encrypted remote can't be pulled into a plain root that holds backups
+        let nas = home.path().join("nas");
+        crypto::init(&nas, b"pw", true).unwrap();
+        fs::create_dir_all(nas.join("2026-03-20_10-00-00")).unwrap();
+        fs::write(nas.join("2026-03-20_10-00-00").join(manifest::MANIFEST), r#"{"files":{}}"#).unwrap();
+
+        let policy = prune::Policy { keep_last: Some(1), ..prune::Policy::default() };
+        assert!(prune_backups(policy, false).unwrap_err().contains("not pruned"));
+        assert_eq!(backup_names().unwrap(), vec!["2026-03-24_14-30-00"]);
+        assert!(nas.join("2026-03-20_10-00-00").join(manifest::MANIFEST).is_file());
+    }
+
+    #[test]
+    fn push_backups_requires_a_remote() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        assert!(push_backups().unwrap_err().contains("no remote"));
     }
 
     // ── backup_root ──────────────────────────────────────────────────────────
@@ -617,4 +1998,56 @@ devfs                396        396          0     100% /dev";
         unsafe { std::env::remove_var("HOME") };
         assert_eq!(backup_root(), "./opz-backups");
     }
+
+    #[test]
+    fn backup_root_prefers_env_over_config() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let dir = home.path().join(".config").join("opz-backup");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), "root = \"~/nas/opz\"\n").unwrap();
+
+        assert_eq!(backup_root(), format!("{}/nas/opz", home.path().display()));
+
+        unsafe { std::env::set_var("OPZ_BACKUP_ROOT", "/mnt/ssd/opz") };
+        let root = backup_root();
+        unsafe { std::env::remove_var("OPZ_BACKUP_ROOT") };
+        assert_eq!(root, "/mnt/ssd/opz");
+    }
+
+    // ── Config::load ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn config_load_uses_xdg_config_home() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let xdg = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("XDG_CONFIG_HOME", xdg.path()) };
+        fs::create_dir_all(xdg.path().join("opz-backup")).unwrap();
+        fs::write(xdg.path().join("opz-backup").join("config.toml"), "device_name = \"OPZ\"\n").unwrap();
+
+        let config = Config::load();
+        unsafe { std::env::remove_var("XDG_CONFIG_HOME") };
+        assert_eq!(config.unwrap().device_name, "OPZ");
+    }
+
+    #[test]
+    fn config_load_missing_file_is_default() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        assert_eq!(Config::load().unwrap(), Config::default());
+    }
+
+    #[test]
+    fn config_load_reports_malformed_file() {
+        let _lock = HOME_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let dir = home.path().join(".config").join("opz-backup");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), "verify = maybe").unwrap();
+
+        assert!(Config::load().unwrap_err().contains("config.toml"));
+    }
 }
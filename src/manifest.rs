@@ -0,0 +1,202 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+use std::time::UNIX_EPOCH;
+
+pub const MANIFEST: &str = "manifest.json";
+
+// Tags of snapshots that aren't regular backups
+pub const PRE_RESTORE: &str = "pre-restore";
+pub const PROJECT_TAG: &str = "project";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// opz-backup version that wrote this manifest
+    #[serde(default)]
+    pub version: String,
+    /// Mount point the files were read from
+    #[serde(default)]
+    pub source: String,
+    /// Set when post-copy verification found the backup differs from the device
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failed: bool,
+    /// Why the snapshot was taken when it isn't a regular backup, e.g. "pre-restore"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// For pre-restore snapshots: the backup that was about to be restored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restoring: Option<String>,
+    /// Name given with `project save --name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(deserialize_with = "files")]
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    /// Modification time, seconds since the Unix epoch
+    #[serde(default)]
+    pub mtime: i64,
+    /// Content hash (sha256, hex); absent for plain-copy backups made before the object store
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "hash")]
+    pub hash: Option<String>,
+}
+
+// Manifests come from archives and remotes other people wrote, and every rel gets joined
+// onto a destination folder: only plain relative paths are accepted
+pub fn check_rel(rel: &str) -> Result<(), String> {
+    match !rel.is_empty() && Path::new(rel).components().all(|c| matches!(c, Component::Normal(_))) {
+        true => Ok(()),
+        false => Err(format!("unsafe path in manifest: {}", rel)),
+    }
+}
+
+// Hashes name object files, so anything but 64 lowercase hex digits is refused too
+pub fn check_hash(hash: &str) -> Result<(), String> {
+    match hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        true => Ok(()),
+        false => Err(format!("bad content hash in manifest: {}", hash)),
+    }
+}
+
+fn hash<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    let hash = Option::<String>::deserialize(d)?;
+    hash.as_deref().map(check_hash).transpose().map_err(serde::de::Error::custom)?;
+    Ok(hash)
+}
+
+fn files<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeMap<String, FileEntry>, D::Error> {
+    let files = BTreeMap::<String, FileEntry>::deserialize(d)?;
+    files.keys().try_for_each(|rel| check_rel(rel)).map_err(serde::de::Error::custom)?;
+    Ok(files)
+}
+
+// Seconds since the epoch; 0 when the filesystem can't tell us
+pub fn mtime(meta: &std::fs::Metadata) -> i64 {
+    meta.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+impl Manifest {
+    pub fn new(source: &str) -> Manifest {
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            source: source.to_string(),
+            failed: false,
+            tag: None,
+            restoring: None,
+            label: None,
+            files: BTreeMap::new(),
+        }
+    }
+
+    // Manifests are read and written as bytes, wherever they're kept (see Backend)
+    pub fn parse(bytes: &[u8]) -> Result<Manifest, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec_pretty(self).map_err(|e| e.to_string())
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.files.values().map(|f| f.size).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(dir: &Path) -> Result<Manifest, String> {
+        Manifest::parse(&std::fs::read(dir.join(MANIFEST)).map_err(|e| e.to_string())?)
+    }
+
+    fn entry(size: u64, digit: char) -> FileEntry {
+        FileEntry { size, mtime: 1_700_000_000, hash: Some(digit.to_string().repeat(64)) }
+    }
+
+    #[test]
+    fn save_then_load_roundtrips() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut m = Manifest::new("/Volumes/OP-Z");
+        m.files.insert("projects/project01.opz".into(), entry(10, 'a'));
+        m.files.insert("samplepacks/1-kick/01/kick.aif".into(), entry(20, 'b'));
+
+        std::fs::write(dir.path().join(MANIFEST), m.to_json().unwrap()).unwrap();
+        assert_eq!(load(dir.path()).unwrap(), m);
+    }
+
+    #[test]
+    fn new_records_tool_version_and_source() {
+        let m = Manifest::new("/media/me/OP-Z");
+        assert_eq!(m.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(m.source, "/media/me/OP-Z");
+    }
+
+    #[test]
+    fn load_accepts_manifest_without_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = "c".repeat(64);
+        std::fs::write(dir.path().join(MANIFEST), format!(r#"{{"files":{{"a":{{"size":2,"hash":"{}"}}}}}}"#, hash)).unwrap();
+        let m = load(dir.path()).unwrap();
+        assert_eq!(m.files["a"], FileEntry { size: 2, mtime: 0, hash: Some(hash) });
+        assert_eq!(m.version, "");
+        assert!(!m.failed);
+    }
+
+    #[test]
+    fn load_rejects_paths_leaving_the_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        for rel in ["../../.bashrc", "/etc/passwd", "projects/../../x", "./a", ""] {
+            let json = serde_json::json!({ "files": { rel: { "size": 1 } } });
+            std::fs::write(dir.path().join(MANIFEST), json.to_string()).unwrap();
+            let e = load(dir.path()).unwrap_err();
+            assert!(e.contains("unsafe path"), "{}: {}", rel, e);
+        }
+    }
+
+    #[test]
+    fn load_rejects_hashes_that_are_not_sha256_hex() {
+        let dir = tempfile::tempdir().unwrap();
+        for hash in ["", "a", "é", "../../x", &"A".repeat(64), &"a".repeat(63), &"a".repeat(65)] {
+            let json = serde_json::json!({ "files": { "a": { "size": 1, "hash": hash } } });
+            std::fs::write(dir.path().join(MANIFEST), json.to_string()).unwrap();
+            let e = load(dir.path()).unwrap_err();
+            assert!(e.contains("bad content hash"), "{}: {}", hash, e);
+        }
+    }
+
+    #[test]
+    fn mtime_reads_file_modification_time() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("f"), b"x").unwrap();
+        assert!(mtime(&std::fs::metadata(dir.path().join("f")).unwrap()) > 1_600_000_000);
+    }
+
+    #[test]
+    fn load_errors_when_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load(dir.path()).is_err());
+    }
+
+    #[test]
+    fn total_size_sums_entries() {
+        let mut m = Manifest::default();
+        m.files.insert("a".into(), entry(3, 'a'));
+        m.files.insert("b".into(), entry(4, 'b'));
+        assert_eq!(m.total_size(), 7);
+    }
+
+    #[test]
+    fn hash_omitted_when_absent() {
+        let mut m = Manifest::default();
+        m.files.insert("a".into(), FileEntry { size: 1, mtime: 0, hash: None });
+        let text = serde_json::to_string(&m).unwrap();
+        assert!(!text.contains("hash"));
+    }
+}
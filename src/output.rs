@@ -0,0 +1,229 @@
+use crate::aiff::Pack;
+use crate::content::Item;
+use crate::diff::{Change, ItemChange};
+use crate::store::{Check, Snapshot};
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Aligned, human-readable text
+    #[default]
+    Text,
+    /// One JSON document on stdout
+    Json,
+    /// Tab-separated rows with a header line
+    Tsv,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupRecord {
+    pub name: String,
+    /// Local time parsed from the backup name, if it follows the usual pattern
+    pub timestamp: Option<String>,
+    pub bytes: u64,
+    pub files: usize,
+    pub failed: bool,
+    pub tag: Option<String>,
+    /// For pre-restore snapshots: the backup whose restore they guard
+    pub restoring: Option<String>,
+    /// For project saves: the name given with --name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Drum packs, with `list --detail`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub packs: Vec<PackRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackRecord {
+    /// e.g. "kick slot 3"
+    pub slot: String,
+    pub path: String,
+    pub name: String,
+    pub slices: usize,
+    pub seconds: f64,
+}
+
+impl PackRecord {
+    pub fn new(path: &str, pack: &Pack) -> PackRecord {
+        PackRecord {
+            slot: Item::classify(path).to_string(),
+            path: path.to_string(),
+            name: pack.name.clone(),
+            slices: pack.slices,
+            seconds: pack.seconds,
+        }
+    }
+}
+
+impl BackupRecord {
+    pub const TSV_HEADER: &str = "name\ttimestamp\tbytes\tfiles\tfailed\ttag";
+
+    pub fn new(snap: &Snapshot) -> BackupRecord {
+        BackupRecord {
+            name: snap.name.clone(),
+            timestamp: timestamp(&snap.name),
+            bytes: snap.manifest.total_size(),
+            files: snap.manifest.files.len(),
+            failed: snap.manifest.failed,
+            tag: snap.manifest.tag.clone(),
+            restoring: snap.manifest.restoring.clone(),
+            label: snap.manifest.label.clone(),
+            packs: Vec::new(),
+        }
+    }
+
+    pub fn tsv(&self) -> String {
+        format!("{}\t{}\t{}\t{}\t{}\t{}",
+            self.name, self.timestamp.as_deref().unwrap_or(""), self.bytes, self.files, self.failed,
+            self.tag.as_deref().unwrap_or(""))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusRecord {
+    pub connected: bool,
+    pub mount: Option<String>,
+    pub root: String,
+    pub backups: usize,
+    pub total_bytes: u64,
+    pub last_backup: Option<BackupRecord>,
+    /// Last time a backup was skipped because the OP-Z was unchanged
+    pub last_check: Option<Check>,
+}
+
+impl StatusRecord {
+    // key<TAB>value rows; last_backup fields are flattened with a `last_` prefix
+    pub fn tsv(&self) -> String {
+        let mut rows = vec![
+            format!("connected\t{}", self.connected),
+            format!("mount\t{}", self.mount.as_deref().unwrap_or("")),
+            format!("root\t{}", self.root),
+            format!("backups\t{}", self.backups),
+            format!("total_bytes\t{}", self.total_bytes),
+        ];
+        if let Some(last) = &self.last_backup {
+            rows.push(format!("last_name\t{}", last.name));
+            rows.push(format!("last_timestamp\t{}", last.timestamp.as_deref().unwrap_or("")));
+            rows.push(format!("last_bytes\t{}", last.bytes));
+            rows.push(format!("last_files\t{}", last.files));
+        }
+        if let Some(check) = &self.last_check {
+            rows.push(format!("checked_time\t{}", check.time));
+            rows.push(format!("checked_snapshot\t{}", check.snapshot));
+        }
+        rows.join("\n")
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffRecord {
+    pub from: String,
+    pub to: String,
+    pub changes: Vec<Change>,
+    /// The same changes per project, slot and config file; left out with --raw
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub summary: Vec<ItemChange>,
+}
+
+impl DiffRecord {
+    pub const TSV_HEADER: &str = "kind\tpath\told_bytes\tnew_bytes";
+
+    pub fn tsv_rows(&self) -> Vec<String> {
+        self.changes.iter().map(|c| match c {
+            Change::Added { path, size } => format!("added\t{}\t\t{}", path, size),
+            Change::Removed { path, size } => format!("removed\t{}\t{}\t", path, size),
+            Change::Modified { path, old_size, new_size } => format!("modified\t{}\t{}\t{}", path, old_size, new_size),
+        }).collect()
+    }
+}
+
+// Backup names carry their local time; expose it ISO-8601 style
+pub fn timestamp(name: &str) -> Option<String> {
+    crate::store::name_time(name).map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> Result<(), String> {
+    println!("{}", serde_json::to_string_pretty(value).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{FileEntry, Manifest};
+
+    fn snapshot(name: &str) -> Snapshot {
+        let mut manifest = Manifest::new("/Volumes/OP-Z");
+        manifest.files.insert("a".into(), FileEntry { size: 100, mtime: 1, hash: Some("aa".into()) });
+        manifest.files.insert("b".into(), FileEntry { size: 23, mtime: 1, hash: Some("bb".into()) });
+        Snapshot { name: name.into(), manifest, plain: None, archive: None }
+    }
+
+    // ── timestamp ───────────────────────────────────────────────────────────
+
+    #[test]
+    fn timestamp_parses_backup_names() {
+        assert_eq!(timestamp("2026-03-24_14-30-00"), Some("2026-03-24T14:30:00".into()));
+    }
+
+    #[test]
+    fn timestamp_none_for_other_names() {
+        assert_eq!(timestamp("my-backup"), None);
+        assert_eq!(timestamp("2026-13-24_14-30-00"), None);
+    }
+
+    // ── records ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn backup_record_has_exact_bytes_and_file_count() {
+        let r = BackupRecord::new(&snapshot("2026-03-24_14-30-00"));
+        assert_eq!((r.bytes, r.files, r.failed), (123, 2, false));
+        assert_eq!(r.tsv(), "2026-03-24_14-30-00\t2026-03-24T14:30:00\t123\t2\tfalse\t");
+    }
+
+    #[test]
+    fn backup_record_json_shape() {
+        let r = BackupRecord::new(&snapshot("x"));
+        let v: serde_json::Value = serde_json::to_value(&r).unwrap();
+        assert_eq!(v["name"], "x");
+        assert_eq!(v["timestamp"], serde_json::Value::Null);
+        assert_eq!(v["bytes"], 123);
+    }
+
+    #[test]
+    fn status_record_tsv_includes_last_backup() {
+        let r = StatusRecord {
+            connected: true,
+            mount: Some("/Volumes/OP-Z".into()),
+            root: "/home/me/opz-backups".into(),
+            backups: 1,
+            total_bytes: 123,
+            last_backup: Some(BackupRecord::new(&snapshot("2026-03-24_14-30-00"))),
+            last_check: Some(Check { time: "2026-03-25T09:00:00".into(), snapshot: "2026-03-24_14-30-00".into() }),
+        };
+        let tsv = r.tsv();
+        assert!(tsv.starts_with("connected\ttrue\nmount\t/Volumes/OP-Z\n"));
+        assert!(tsv.contains("last_name\t2026-03-24_14-30-00"));
+        assert!(tsv.ends_with("checked_time\t2026-03-25T09:00:00\nchecked_snapshot\t2026-03-24_14-30-00"));
+    }
+
+    #[test]
+    fn diff_record_tags_change_kinds() {
+        let r = DiffRecord {
+            from: "a".into(),
+            to: "b".into(),
+            changes: vec![
+                Change::Removed { path: "x".into(), size: 1 },
+                Change::Added { path: "y".into(), size: 2 },
+                Change::Modified { path: "z".into(), old_size: 3, new_size: 4 },
+            ],
+            summary: Vec::new(),
+        };
+        let v: serde_json::Value = serde_json::to_value(&r).unwrap();
+        assert_eq!(v["changes"][0]["kind"], "removed");
+        assert_eq!(v["changes"][2]["old_size"], 3);
+        assert_eq!(r.tsv_rows(), vec!["removed\tx\t1\t", "added\ty\t\t2", "modified\tz\t3\t4"]);
+    }
+}
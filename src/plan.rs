@@ -0,0 +1,159 @@
+use crate::detect::CONTENT;
+use crate::diff::{self, Change};
+use crate::manifest::Manifest;
+use std::collections::HashSet;
+use std::path::Path;
+
+// What restoring a snapshot onto the device would do, file by file
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    /// In the backup and on the device, but different: (path, device size, backup size)
+    pub overwrite: Vec<(String, u64, u64)>,
+    /// In the backup only
+    pub add: Vec<(String, u64)>,
+    /// Identical on both sides: (count, bytes)
+    pub untouched: (usize, u64),
+    /// On the device only; a normal restore leaves these in place
+    pub device_only: Vec<(String, u64)>,
+    /// On the device only and inside a content folder; a mirror restore deletes these
+    pub delete: Vec<(String, u64)>,
+}
+
+impl Plan {
+    pub fn is_noop(&self) -> bool {
+        self.overwrite.is_empty() && self.add.is_empty() && self.delete.is_empty()
+    }
+
+    pub fn overwrite_bytes(&self) -> u64 {
+        self.overwrite.iter().map(|(_, _, new)| new).sum()
+    }
+
+    pub fn add_bytes(&self) -> u64 {
+        self.add.iter().map(|(_, size)| size).sum()
+    }
+
+    pub fn device_only_bytes(&self) -> u64 {
+        self.device_only.iter().map(|(_, size)| size).sum()
+    }
+
+    pub fn delete_bytes(&self) -> u64 {
+        self.delete.iter().map(|(_, size)| size).sum()
+    }
+
+    // The files a restore has to write; untouched ones are left as they are
+    pub fn writes(&self) -> HashSet<&str> {
+        self.overwrite.iter().map(|(path, _, _)| path.as_str())
+            .chain(self.add.iter().map(|(path, _)| path.as_str()))
+            .collect()
+    }
+
+    // Removes the files scheduled for deletion under dst; folders are left in place
+    pub fn delete_from(&self, dst: &Path) -> Result<(), String> {
+        for (path, _) in &self.delete {
+            let target = dst.join(path);
+            std::fs::remove_file(&target).map_err(|e| format!("{}: {}", target.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+fn in_content_folder(path: &str) -> bool {
+    path.split_once('/').is_some_and(|(top, _)| CONTENT.contains(&top))
+}
+
+// Same tree comparison as `diff`, with the device as the old side and the backup as the new.
+// With `mirror`, device-only files in the content folders are scheduled for deletion.
+pub fn restore_plan(device: &Manifest, backup: &Manifest, mirror: bool) -> Plan {
+    let mut plan = Plan::default();
+    for change in diff::compare(&device.files, &backup.files) {
+        match change {
+            Change::Modified { path, old_size, new_size } => plan.overwrite.push((path, old_size, new_size)),
+            Change::Added { path, size } => plan.add.push((path, size)),
+            Change::Removed { path, size } if mirror && in_content_folder(&path) => plan.delete.push((path, size)),
+            Change::Removed { path, size } => plan.device_only.push((path, size)),
+        }
+    }
+    let changed = plan.overwrite.len() + plan.add.len();
+    let touched: u64 = plan.overwrite_bytes() + plan.add_bytes();
+    plan.untouched = (backup.files.len() - changed, backup.total_size() - touched);
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileEntry;
+
+    fn manifest(entries: &[(&str, u64, i64)]) -> Manifest {
+        let mut m = Manifest::default();
+        for (path, size, mtime) in entries {
+            m.files.insert(path.to_string(), FileEntry { size: *size, mtime: *mtime, hash: None });
+        }
+        m
+    }
+
+    #[test]
+    fn restore_plan_sorts_files_into_buckets() {
+        let device = manifest(&[
+            ("projects/project01.opz", 10, 200),
+            ("projects/project02.opz", 10, 100),
+            ("samplepacks/2-snare/05/new.aif", 7, 300),
+        ]);
+        let backup = manifest(&[
+            ("projects/project01.opz", 10, 100),
+            ("projects/project02.opz", 10, 100),
+            ("samplepacks/1-kick/03/808.aif", 20, 100),
+        ]);
+
+        let plan = restore_plan(&device, &backup, false);
+        assert_eq!(plan.overwrite, vec![("projects/project01.opz".to_string(), 10, 10)]);
+        assert_eq!(plan.add, vec![("samplepacks/1-kick/03/808.aif".to_string(), 20)]);
+        assert_eq!(plan.device_only, vec![("samplepacks/2-snare/05/new.aif".to_string(), 7)]);
+        assert_eq!(plan.untouched, (1, 10));
+        assert_eq!((plan.overwrite_bytes(), plan.add_bytes(), plan.device_only_bytes()), (10, 20, 7));
+        assert!(!plan.is_noop());
+        assert_eq!(plan.writes(), HashSet::from(["projects/project01.opz", "samplepacks/1-kick/03/808.aif"]));
+    }
+
+    #[test]
+    fn restore_plan_identical_trees_is_noop() {
+        let m = manifest(&[("projects/project01.opz", 10, 100)]);
+        let plan = restore_plan(&m, &m.clone(), false);
+        assert!(plan.is_noop());
+        assert_eq!(plan.untouched, (1, 10));
+    }
+
+    #[test]
+    fn restore_plan_mirror_deletes_only_inside_content_folders() {
+        let device = manifest(&[
+            ("projects/project09.opz", 10, 100),
+            ("samplepacks/2-snare/05/new.aif", 7, 300),
+            ("config/general.json", 3, 100),
+            ("import/pending.aif", 4, 100),
+            ("stray.txt", 1, 100),
+        ]);
+        let backup = manifest(&[]);
+
+        let plan = restore_plan(&device, &backup, true);
+        assert_eq!(plan.delete, vec![
+            ("projects/project09.opz".to_string(), 10),
+            ("samplepacks/2-snare/05/new.aif".to_string(), 7),
+        ]);
+        assert_eq!(plan.device_only.len(), 3);
+        assert_eq!(plan.delete_bytes(), 17);
+        assert!(!plan.is_noop());
+    }
+
+    #[test]
+    fn delete_from_removes_scheduled_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("projects")).unwrap();
+        std::fs::write(dir.path().join("projects/project09.opz"), b"x").unwrap();
+        std::fs::write(dir.path().join("projects/project01.opz"), b"y").unwrap();
+
+        let plan = Plan { delete: vec![("projects/project09.opz".to_string(), 1)], ..Plan::default() };
+        plan.delete_from(dir.path()).unwrap();
+        assert!(!dir.path().join("projects/project09.opz").exists());
+        assert!(dir.path().join("projects/project01.opz").exists());
+    }
+}
@@ -0,0 +1,359 @@
+use crate::backend;
+use crate::manifest::{PRE_RESTORE, PROJECT_TAG};
+use crate::store::{self, Snapshot};
+use chrono::{Datelike, NaiveDateTime};
+use clap::Args;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+// GFS retention. Also the `[retention]` table of the config file, where `auto = true`
+// prunes after every backup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Args)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Keep the N most recent backups
+    #[arg(long, value_name = "N")]
+    pub keep_last: Option<usize>,
+    /// Keep the newest backup of each of the last N days that have one
+    #[arg(long, value_name = "N")]
+    pub keep_daily: Option<usize>,
+    /// Keep the newest backup of each of the last N weeks that have one
+    #[arg(long, value_name = "N")]
+    pub keep_weekly: Option<usize>,
+    /// Keep the newest backup of each of the last N months that have one
+    #[arg(long, value_name = "N")]
+    pub keep_monthly: Option<usize>,
+    #[arg(skip)]
+    pub auto: bool,
+}
+
+impl Policy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none() && self.keep_daily.is_none()
+            && self.keep_weekly.is_none() && self.keep_monthly.is_none()
+    }
+}
+
+// Walks newest → oldest keeping the first backup seen in each new bucket until n are kept
+fn keep_buckets<K: PartialEq>(
+    dated: &[(&String, NaiveDateTime)],
+    n: usize,
+    bucket: impl Fn(&NaiveDateTime) -> K,
+    keep: &mut BTreeSet<String>,
+) {
+    let mut last: Option<K> = None;
+    let mut kept = 0;
+    for (name, time) in dated {
+        if kept == n { break; }
+        let b = bucket(time);
+        if last.as_ref() != Some(&b) {
+            keep.insert(name.to_string());
+            kept += 1;
+            last = Some(b);
+        }
+    }
+}
+
+// What retention looks at besides the name, from the backup's manifest
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub tag: Option<String>,
+    pub restoring: Option<String>,
+    pub failed: bool,
+}
+
+impl Entry {
+    pub fn of(snap: &Snapshot) -> Entry {
+        Entry {
+            name: snap.name.clone(),
+            tag: snap.manifest.tag.clone(),
+            restoring: snap.manifest.restoring.clone(),
+            failed: snap.manifest.failed,
+        }
+    }
+
+    // A whole, verified copy of the OP-Z: what the policy's slots are filled with
+    fn is_regular(&self) -> bool {
+        !self.failed && !matches!(self.tag.as_deref(), Some(PRE_RESTORE | PROJECT_TAG))
+    }
+}
+
+// Names the policy keeps, from backups sorted oldest first. Backups whose name isn't a
+// timestamp are never pruned, since we can't tell how old they are. Only regular backups
+// fill the policy's slots; the others are kept around them:
+// - failed backups and project saves no older than the oldest backup kept
+// - the newest save of each project slot
+// - copies taken before a restore, while the backup restored is kept, and the newest
+//   pre-restore snapshot so undo-restore works
+pub fn select_keep(backups: &[Entry], policy: &Policy) -> BTreeSet<String> {
+    let mut keep: BTreeSet<String> = backups.iter()
+        .filter(|b| store::name_time(&b.name).is_none())
+        .map(|b| b.name.clone())
+        .collect();
+    let dated: Vec<(&String, NaiveDateTime)> = backups.iter().rev()
+        .filter(|b| b.is_regular())
+        .filter_map(|b| Some((&b.name, store::name_time(&b.name)?)))
+        .collect();
+
+    if let Some(n) = policy.keep_last {
+        keep.extend(dated.iter().take(n).map(|(name, _)| name.to_string()));
+    }
+    if let Some(n) = policy.keep_daily {
+        keep_buckets(&dated, n, |t| t.date(), &mut keep);
+    }
+    if let Some(n) = policy.keep_weekly {
+        keep_buckets(&dated, n, |t| t.iso_week(), &mut keep);
+    }
+    if let Some(n) = policy.keep_monthly {
+        keep_buckets(&dated, n, |t| (t.year(), t.month()), &mut keep);
+    }
+
+    let since = dated.iter().filter(|(name, _)| keep.contains(*name)).map(|(_, t)| *t).min();
+    let mut slots = BTreeSet::new();
+    for b in backups.iter().rev().filter(|b| !b.is_regular() && b.restoring.is_none()) {
+        // The name after the timestamp tells the slot, e.g. "-project07"
+        let newest_of_slot = b.tag.as_deref() == Some(PROJECT_TAG) && slots.insert(b.name.get(19..));
+        let in_window = matches!((store::name_time(&b.name), since), (Some(t), Some(s)) if t >= s);
+        if newest_of_slot || in_window {
+            keep.insert(b.name.clone());
+        }
+    }
+    keep.extend(backups.iter().rev().find(|b| b.tag.as_deref() == Some(PRE_RESTORE)).map(|b| b.name.clone()));
+    // Oldest first, so a copy guarding another copy sees whether that one stays
+    for b in backups {
+        if b.restoring.as_ref().is_some_and(|r| keep.contains(r)) {
+            keep.insert(b.name.clone());
+        }
+    }
+    keep
+}
+
+#[derive(Debug)]
+pub struct Plan {
+    pub keep: Vec<String>,
+    pub remove: Vec<String>,
+    /// Bytes on disk released: dropped plain copies and archives plus objects nothing kept refers to
+    pub freed: u64,
+}
+
+pub fn plan(root: &Path, names: &[String], policy: &Policy) -> Result<Plan, String> {
+    let backups = names.iter()
+        .map(|name| Snapshot::load(root, name).map(|snap| Entry::of(&snap)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let keep = select_keep(&backups, policy);
+    let (keep, remove): (Vec<String>, Vec<String>) = names.iter().cloned().partition(|n| keep.contains(n));
+
+    let mut freed: u64 = store::unreferenced(root, &store::referenced(root, &keep)?)
+        .iter()
+        .map(|(_, size)| size)
+        .sum();
+    for name in &remove {
+        freed += Snapshot::load(root, name)?.own_size();
+    }
+    Ok(Plan { keep, remove, freed })
+}
+
+// Deletes the snapshots, then every object left without a snapshot pointing at it. The
+// root is pruned like a remote, through its Local backend; only plain copies, which
+// never leave the local disk, are removed directly.
+pub fn apply(root: &Path, plan: &Plan) -> Result<(), String> {
+    for name in &plan.remove {
+        if let Some(dir) = Snapshot::load(root, name)?.plain {
+            std::fs::remove_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+    }
+    backend::prune(root, plan, &backend::Local::new(root)).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn entries(list: &[&str]) -> Vec<Entry> {
+        list.iter().map(|name| Entry { name: name.to_string(), ..Entry::default() }).collect()
+    }
+
+    fn keep(list: &[&str], policy: Policy) -> Vec<String> {
+        select_keep(&entries(list), &policy).into_iter().collect()
+    }
+
+    // ── select_keep ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn keep_last_keeps_newest() {
+        let policy = Policy { keep_last: Some(2), ..Policy::default() };
+        assert_eq!(
+            keep(&["2026-03-01_10-00-00", "2026-03-02_10-00-00", "2026-03-03_10-00-00"], policy),
+            names(&["2026-03-02_10-00-00", "2026-03-03_10-00-00"])
+        );
+    }
+
+    #[test]
+    fn keep_daily_takes_newest_per_day() {
+        let policy = Policy { keep_daily: Some(2), ..Policy::default() };
+        assert_eq!(
+            keep(&[
+                "2026-03-01_10-00-00",
+                "2026-03-02_09-00-00",
+                "2026-03-02_18-00-00",
+                "2026-03-03_08-00-00",
+                "2026-03-03_12-00-00",
+            ], policy),
+            names(&["2026-03-02_18-00-00", "2026-03-03_12-00-00"])
+        );
+    }
+
+    #[test]
+    fn keep_weekly_and_monthly_combine() {
+        let policy = Policy { keep_weekly: Some(1), keep_monthly: Some(3), ..Policy::default() };
+        assert_eq!(
+            keep(&[
+                "2026-01-10_10-00-00",
+                "2026-01-20_10-00-00",
+                "2026-02-05_10-00-00",
+                "2026-03-02_10-00-00",
+                "2026-03-03_10-00-00",
+            ], policy),
+            names(&["2026-01-20_10-00-00", "2026-02-05_10-00-00", "2026-03-03_10-00-00"])
+        );
+    }
+
+    #[test]
+    fn undated_names_are_always_kept() {
+        let policy = Policy { keep_last: Some(0), ..Policy::default() };
+        assert_eq!(keep(&["2026-03-01_10-00-00", "imported"], policy), names(&["imported"]));
+    }
+
+    #[test]
+    fn pre_restore_snapshots_never_take_a_backups_place() {
+        let mut backups = entries(&[
+            "2026-03-01_10-00-00-pre-restore",
+            "2026-03-02_09-00-00",
+            "2026-03-02_18-00-00-pre-restore",
+            "2026-03-02_18-05-00-pre-restore",
+        ]);
+        for b in backups.iter_mut().filter(|b| b.name.ends_with(PRE_RESTORE)) {
+            b.tag = Some(PRE_RESTORE.to_string());
+            b.restoring = Some("2026-01-01_10-00-00".to_string());
+        }
+        let policy = Policy { keep_daily: Some(1), ..Policy::default() };
+        assert_eq!(
+            select_keep(&backups, &policy).into_iter().collect::<Vec<_>>(),
+            names(&["2026-03-02_09-00-00", "2026-03-02_18-05-00-pre-restore"])
+        );
+        let policy = Policy { keep_last: Some(0), ..Policy::default() };
+        assert_eq!(
+            select_keep(&backups, &policy).into_iter().collect::<Vec<_>>(),
+            names(&["2026-03-02_18-05-00-pre-restore"])
+        );
+    }
+
+    #[test]
+    fn project_saves_and_failed_backups_never_take_a_backups_place() {
+        let mut backups = entries(&[
+            "2026-03-01_10-00-00-project03",
+            "2026-03-01_11-00-00-project07",
+            "2026-03-02_10-00-00",
+            "2026-03-02_12-00-00-project07",
+            "2026-03-03_10-00-00",
+            "2026-03-03_11-00-00-project07",
+            "2026-03-03_12-00-00",
+        ]);
+        for b in backups.iter_mut().filter(|b| b.name.contains(PROJECT_TAG)) {
+            b.tag = Some(PROJECT_TAG.to_string());
+        }
+        backups[6].failed = true;
+        let policy = Policy { keep_last: Some(1), keep_daily: Some(1), ..Policy::default() };
+        assert_eq!(
+            select_keep(&backups, &policy).into_iter().collect::<Vec<_>>(),
+            names(&[
+                "2026-03-01_10-00-00-project03",
+                "2026-03-03_10-00-00",
+                "2026-03-03_11-00-00-project07",
+                "2026-03-03_12-00-00",
+            ])
+        );
+    }
+
+    #[test]
+    fn restore_copies_stay_while_the_backup_restored_does() {
+        let mut backups = entries(&[
+            "2026-03-01_10-00-00",
+            "2026-03-01_12-00-00-pre-restore",
+            "2026-03-02_10-00-00",
+            "2026-03-02_11-00-00-project05",
+            "2026-03-02_12-00-00-project05",
+            "2026-03-03_10-00-00-pre-restore",
+        ]);
+        let guards = [(1, PRE_RESTORE, "2026-03-01_10-00-00"), (4, PROJECT_TAG, "2026-03-02_11-00-00-project05"),
+            (5, PRE_RESTORE, "2026-03-02_10-00-00")];
+        for (i, tag, restoring) in guards {
+            backups[i].tag = Some(tag.to_string());
+            backups[i].restoring = Some(restoring.to_string());
+        }
+        backups[3].tag = Some(PROJECT_TAG.to_string());
+
+        let policy = Policy { keep_last: Some(2), ..Policy::default() };
+        assert_eq!(select_keep(&backups, &policy).len(), 6);
+        let policy = Policy { keep_last: Some(1), ..Policy::default() };
+        assert_eq!(
+            select_keep(&backups, &policy).into_iter().collect::<Vec<_>>(),
+            names(&[
+                "2026-03-02_10-00-00",
+                "2026-03-02_11-00-00-project05",
+                "2026-03-02_12-00-00-project05",
+                "2026-03-03_10-00-00-pre-restore",
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_policy_detected() {
+        assert!(Policy::default().is_empty());
+        assert!(!Policy { keep_monthly: Some(1), ..Policy::default() }.is_empty());
+    }
+
+    // ── plan / apply ────────────────────────────────────────────────────────
+
+    #[test]
+    fn plan_and_apply_free_only_unshared_objects() {
+        let src = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("kick.aif"), b"shared sample").unwrap();
+        fs::write(src.path().join("project01.opz"), b"v1").unwrap();
+        store::store_snapshot(root.path(), src.path(), "2026-03-01_10-00-00").unwrap();
+        fs::write(src.path().join("project01.opz"), b"v2 longer").unwrap();
+        store::store_snapshot(root.path(), src.path(), "2026-03-02_10-00-00").unwrap();
+
+        let all = names(&["2026-03-01_10-00-00", "2026-03-02_10-00-00"]);
+        let policy = Policy { keep_last: Some(1), ..Policy::default() };
+        let plan = plan(root.path(), &all, &policy).unwrap();
+        assert_eq!(plan.remove, names(&["2026-03-01_10-00-00"]));
+        assert_eq!(plan.freed, 2);
+
+        apply(root.path(), &plan).unwrap();
+        assert!(!root.path().join("2026-03-01_10-00-00").exists());
+        assert_eq!(store::stored_size(root.path()), 13 + 9);
+        let snap = Snapshot::load(root.path(), "2026-03-02_10-00-00").unwrap();
+        assert!(store::verify(root.path(), &snap).unwrap().is_clean());
+    }
+
+    #[test]
+    fn plan_counts_plain_copies_in_full() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("2026-03-01_10-00-00")).unwrap();
+        fs::write(root.path().join("2026-03-01_10-00-00/a"), vec![0u8; 50]).unwrap();
+        fs::create_dir_all(root.path().join("2026-03-02_10-00-00")).unwrap();
+
+        let all = names(&["2026-03-01_10-00-00", "2026-03-02_10-00-00"]);
+        let plan = plan(root.path(), &all, &Policy { keep_last: Some(1), ..Policy::default() }).unwrap();
+        assert_eq!(plan.freed, 50);
+    }
+}
@@ -0,0 +1,79 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// The OP-Z keeps its settings as JSON in config/ (general.json, midi.json, dmx.json).
+// Flattened, every setting gets a dotted key named after its file: midi.channel_track_3.
+pub fn flatten(file: &str, bytes: &[u8]) -> Result<BTreeMap<String, Value>, String> {
+    let value: Value = serde_json::from_slice(bytes).map_err(|e| format!("{}: {}", file, e))?;
+    let stem = file.rsplit('/').next().unwrap_or(file).trim_end_matches(".json");
+    let mut flat = BTreeMap::new();
+    walk(stem, &value, &mut flat);
+    Ok(flat)
+}
+
+// Objects nest into dotted keys; arrays stay whole, they read better as one value
+fn walk(key: &str, value: &Value, flat: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                walk(&format!("{}.{}", key, k), v, flat);
+            }
+        }
+        _ => {
+            flat.insert(key.to_string(), value.clone());
+        }
+    }
+}
+
+// Strings bare, everything else as compact JSON
+pub fn show(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+// "midi.channel_track_3: 3 → 10" per changed setting; "—" stands for a missing one
+pub fn compare(old: &BTreeMap<String, Value>, new: &BTreeMap<String, Value>) -> Vec<String> {
+    let keys: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|k| old.get(*k) != new.get(*k))
+        .map(|k| {
+            let side = |v: Option<&Value>| v.map_or_else(|| "—".to_string(), show);
+            format!("{}: {} → {}", k, side(old.get(k)), side(new.get(k)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ── flatten ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn flatten_names_keys_after_the_file() {
+        let flat = flatten("config/midi.json", br#"{"channel_track_3": 3, "clock": {"in": true, "out": false}, "keys": [1, 2]}"#).unwrap();
+        assert_eq!(flat.keys().collect::<Vec<_>>(), vec!["midi.channel_track_3", "midi.clock.in", "midi.clock.out", "midi.keys"]);
+        assert_eq!(flat["midi.keys"], serde_json::json!([1, 2]));
+    }
+
+    #[test]
+    fn flatten_rejects_non_json() {
+        assert!(flatten("config/general.json", b"\x00\x01").is_err());
+    }
+
+    // ── compare ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn compare_lists_changed_added_and_removed_keys() {
+        let old = flatten("midi.json", br#"{"channel_track_3": 3, "mode": "ext", "gone": 1}"#).unwrap();
+        let new = flatten("midi.json", br#"{"channel_track_3": 10, "mode": "ext", "new": [0]}"#).unwrap();
+        assert_eq!(compare(&old, &new), vec![
+            "midi.channel_track_3: 3 → 10",
+            "midi.gone: 1 → —",
+            "midi.new: — → [0]",
+        ]);
+        assert!(compare(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::backend::{Backend, Entry};
+use ssh2::{CheckResult, KnownHostFileKind, Session};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+// A backup root on an SSH server, e.g. sftp://me@studio.local/srv/opz-backups.
+// Authenticates with the ssh-agent or ~/.ssh/id_ed25519 / id_rsa, and only talks to
+// hosts already in ~/.ssh/known_hosts.
+pub struct Sftp {
+    url: String,
+    sftp: ssh2::Sftp,
+    root: PathBuf,
+}
+
+#[derive(Debug, PartialEq)]
+struct Target {
+    user: String,
+    host: String,
+    port: u16,
+    root: String,
+}
+
+// [user@]host[:port]/path, the part after sftp://. The user defaults to $USER.
+fn parse(rest: &str) -> Result<Target, String> {
+    let (authority, path) = rest.split_once('/').ok_or_else(|| format!("sftp://{}: missing path", rest))?;
+    let (user, hostport) = match authority.split_once('@') {
+        Some((user, hostport)) => (user.to_string(), hostport),
+        None => (std::env::var("USER").unwrap_or_default(), authority),
+    };
+    let (host, port) = match hostport.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("sftp://{}: bad port", rest))?),
+        None => (hostport, 22),
+    };
+    if host.is_empty() || user.is_empty() {
+        return Err(format!("sftp://{}: need user@host", rest));
+    }
+    Ok(Target { user, host: host.to_string(), port, root: format!("/{}", path.trim_end_matches('/')) })
+}
+
+fn check_host_key(session: &Session, t: &Target) -> Result<(), String> {
+    let home = std::env::var("HOME").map_err(|_| "HOME not set".to_string())?;
+    let mut known = session.known_hosts().map_err(|e| e.to_string())?;
+    known.read_file(&Path::new(&home).join(".ssh/known_hosts"), KnownHostFileKind::OpenSSH).ok();
+    let (key, _) = session.host_key().ok_or("server sent no host key")?;
+    match known.check_port(&t.host, t.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(format!("host key for {} does not match ~/.ssh/known_hosts", t.host)),
+        _ => Err(format!("{} is not in ~/.ssh/known_hosts (connect once with ssh first)", t.host)),
+    }
+}
+
+fn authenticate(session: &Session, user: &str) -> Result<(), String> {
+    if session.userauth_agent(user).is_ok() {
+        return Ok(());
+    }
+    let home = std::env::var("HOME").unwrap_or_default();
+    for key in ["id_ed25519", "id_rsa"] {
+        let path = Path::new(&home).join(".ssh").join(key);
+        if path.is_file() && session.userauth_pubkey_file(user, None, &path, None).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(format!("no usable ssh key for {} (tried ssh-agent, ~/.ssh/id_ed25519, ~/.ssh/id_rsa)", user))
+}
+
+impl Sftp {
+    pub fn connect(rest: &str) -> Result<Sftp, String> {
+        let t = parse(rest)?;
+        let url = format!("sftp://{}", rest);
+        let err = |e: &dyn std::fmt::Display| format!("{}: {}", url, e);
+        let tcp = TcpStream::connect((t.host.as_str(), t.port)).map_err(|e| err(&e))?;
+        let mut session = Session::new().map_err(|e| err(&e))?;
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(|e| err(&e))?;
+        check_host_key(&session, &t).map_err(|e| err(&e))?;
+        authenticate(&session, &t.user).map_err(|e| err(&e))?;
+        let sftp = session.sftp().map_err(|e| err(&e))?;
+        Ok(Sftp { url, sftp, root: PathBuf::from(t.root) })
+    }
+
+    fn err(&self, key: &str) -> impl Fn(ssh2::Error) -> String + '_ {
+        let key = key.to_string();
+        move |e| format!("{}/{}: {}", self.url.trim_end_matches('/'), key, e)
+    }
+
+    // mkdir -p
+    fn make_dirs(&self, dir: &Path) -> Result<(), String> {
+        if dir == Path::new("/") || self.sftp.stat(dir).is_ok() {
+            return Ok(());
+        }
+        if let Some(parent) = dir.parent() {
+            self.make_dirs(parent)?;
+        }
+        self.sftp.mkdir(dir, 0o755).map_err(|e| format!("{}: {}", dir.display(), e))
+    }
+}
+
+impl Backend for Sftp {
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+
+    fn list(&self, dir: &str) -> Result<Vec<Entry>, String> {
+        let Ok(entries) = self.sftp.readdir(self.root.join(dir)) else { return Ok(Vec::new()) };
+        Ok(entries.into_iter()
+            .filter_map(|(path, stat)| Some(Entry {
+                name: path.file_name()?.to_str()?.to_string(),
+                size: stat.size.unwrap_or(0),
+                dir: stat.is_dir(),
+            }))
+            .collect())
+    }
+
+    fn read(&self, key: &str) -> Result<Vec<u8>, String> {
+        let mut file = self.sftp.open(self.root.join(key)).map_err(self.err(key))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(|e| format!("{}: {}", key, e))?;
+        Ok(data)
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.root.join(key);
+        self.make_dirs(path.parent().unwrap_or(&self.root))?;
+        let tmp = path.with_file_name(format!(".tmp-{}", std::process::id()));
+        let mut file = self.sftp.create(&tmp).map_err(self.err(key))?;
+        file.write_all(data).map_err(|e| format!("{}: {}", key, e))?;
+        drop(file);
+        // OpenSSH's sftp-server won't rename over an existing file
+        if self.sftp.rename(&tmp, &path, None).is_err() {
+            self.sftp.unlink(&path).ok();
+            self.sftp.rename(&tmp, &path, None).map_err(self.err(key))?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let path = self.root.join(key);
+        self.sftp.unlink(&path).map_err(self.err(key))?;
+        if let Some(parent) = path.parent().filter(|p| *p != self.root) {
+            self.sftp.rmdir(parent).ok();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_url() {
+        assert_eq!(parse("me@studio.local:2222/srv/opz/").unwrap(), Target {
+            user: "me".into(), host: "studio.local".into(), port: 2222, root: "/srv/opz".into(),
+        });
+    }
+
+    #[test]
+    fn parse_defaults_port() {
+        let t = parse("me@studio/opz").unwrap();
+        assert_eq!((t.port, t.root.as_str()), (22, "/opz"));
+    }
+
+    #[test]
+    fn parse_rejects_missing_parts() {
+        assert!(parse("me@studio").is_err());
+        assert!(parse("me@:22/opz").is_err());
+        assert!(parse("me@studio:ssh/opz").is_err());
+    }
+}
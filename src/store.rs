@@ -0,0 +1,950 @@
+use crate::backend::{self, Backend, Local};
+use crate::{archive, crypto};
+use crate::manifest::{self, FileEntry, MANIFEST, Manifest};
+use crate::{pct, walk_dir};
+use ml_progress::{Progress, progress};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// File contents live once under <root>/.objects/<2-hex>/<hash>; a snapshot is
+// <root>/<name>/manifest.json mapping relative paths to those hashes.
+pub const OBJECTS: &str = ".objects";
+
+// Takes the place of manifest.json in encrypted roots that don't keep plain metadata
+pub const SEALED_MANIFEST: &str = "manifest.json.enc";
+
+// Snapshot names are the local time they were taken
+pub const NAME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+pub fn name_time(name: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(name.get(..19)?, NAME_FORMAT).ok()
+}
+
+pub struct Snapshot {
+    pub name: String,
+    pub manifest: Manifest,
+    /// Set for full-copy backups made before the object store existed
+    pub plain: Option<PathBuf>,
+    /// Set for snapshots packed into a single .tar.zst / .zip file
+    pub archive: Option<PathBuf>,
+}
+
+impl Snapshot {
+    pub fn load(root: &Path, name: &str) -> Result<Snapshot, String> {
+        let dir = root.join(name);
+        let snap = |manifest, plain, archive| Snapshot { name: name.to_string(), manifest, plain, archive };
+        let store = Local::new(root);
+        if let Some(key) = backend::manifest_key(&store, name)? {
+            let manifest = decode_manifest(root, &key, &store.read(&key)?)
+                .map_err(|e| format!("{}: {}", root.join(&key).display(), e))?;
+            Ok(snap(manifest, None, None))
+        } else if dir.is_dir() {
+            let manifest = Manifest { files: scan(&dir).files, ..Manifest::default() };
+            Ok(snap(manifest, Some(dir), None))
+        } else if let Some(path) = archive::find(root, name) {
+            Ok(snap(archive::read_manifest(&path)?, None, Some(path)))
+        } else {
+            Err(format!("no backup named {}", name))
+        }
+    }
+
+    // Writes back a changed manifest (e.g. the failed flag) wherever the snapshot lives
+    pub fn save_manifest(&self, root: &Path) -> Result<(), String> {
+        match &self.archive {
+            Some(path) => archive::replace_manifest(path, &self.manifest),
+            None => save_manifest(root, &self.name, &self.manifest),
+        }
+    }
+
+    // Reads the bytes of `rel`, decrypting them in an encrypted root
+    pub fn open(&self, root: &Path, rel: &str) -> Result<Box<dyn Read>, String> {
+        match (&self.plain, &self.manifest.files.get(rel).and_then(|f| f.hash.as_deref())) {
+            (Some(dir), _) => {
+                let path = dir.join(rel);
+                Ok(Box::new(std::fs::File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?))
+            }
+            (None, Some(hash)) if self.archive.is_none() => read_object(root, hash),
+            _ => Err(format!("{}: no content hash", rel)),
+        }
+    }
+
+    // All of `rel`, wherever the snapshot keeps it (archives included)
+    pub fn read(&self, root: &Path, rel: &str) -> Result<Vec<u8>, String> {
+        if let Some(path) = &self.archive {
+            return archive::read_file(path, rel);
+        }
+        let mut bytes = Vec::new();
+        self.open(root, rel)?.read_to_end(&mut bytes).map_err(|e| format!("{}: {}", rel, e))?;
+        Ok(bytes)
+    }
+
+    // Bytes the snapshot itself takes on disk, objects aside
+    pub fn own_size(&self) -> u64 {
+        match (&self.plain, &self.archive) {
+            (Some(dir), _) => walk_dir(dir).values().sum(),
+            (_, Some(path)) => std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    // Narrows the snapshot to the selected paths (files, or folders with a trailing
+    // slash or not). Every selection must match something.
+    pub fn retain_paths(&mut self, only: &[String]) -> Result<(), String> {
+        for sel in only {
+            if !self.manifest.files.keys().any(|rel| path_matches(rel, sel)) {
+                return Err(format!("nothing in {} matches {}", self.name, sel));
+            }
+        }
+        self.manifest.files.retain(|rel, _| only.iter().any(|sel| path_matches(rel, sel)));
+        Ok(())
+    }
+
+    // Where the bytes of `rel` can be read from; None inside an archive
+    pub fn content_path(&self, root: &Path, rel: &str) -> Option<PathBuf> {
+        match (&self.plain, &self.archive) {
+            (Some(dir), _) => Some(dir.join(rel)),
+            (_, Some(_)) => None,
+            _ => object_path(root, self.manifest.files.get(rel)?.hash.as_deref()?).ok(),
+        }
+    }
+}
+
+// `projects/project03.opz` matches itself; `samplepacks/1-kick` and `samplepacks/1-kick/`
+// match everything below that folder
+pub fn path_matches(rel: &str, sel: &str) -> bool {
+    let sel = sel.trim_matches('/');
+    rel == sel || rel.strip_prefix(sel).is_some_and(|rest| rest.starts_with('/'))
+}
+
+// Folders (trailing slash) and files down to `depth` levels, in tree order, each with
+// an indented label for display
+pub fn tree_items(files: &BTreeMap<String, FileEntry>, depth: usize) -> Vec<(String, String)> {
+    let mut paths = BTreeSet::new();
+    for rel in files.keys() {
+        let parts: Vec<&str> = rel.split('/').collect();
+        for level in 1..parts.len().min(depth + 1) {
+            paths.insert(format!("{}/", parts[..level].join("/")));
+        }
+        if parts.len() <= depth {
+            paths.insert(rel.clone());
+        }
+    }
+    paths.into_iter()
+        .map(|p| {
+            let trimmed = p.trim_end_matches('/');
+            let level = trimmed.matches('/').count();
+            let leaf = trimmed.rsplit('/').next().unwrap_or(trimmed);
+            let slash = if p.ends_with('/') { "/" } else { "" };
+            let label = format!("{}{}{}", "  ".repeat(level), leaf, slash);
+            (p, label)
+        })
+        .collect()
+}
+
+// Size and mtime of every file under dir, without reading contents (cheap on the device)
+pub fn scan(dir: &Path) -> Manifest {
+    let mut manifest = Manifest::new(&dir.to_string_lossy());
+    for (rel, size) in walk_dir(dir) {
+        let mtime = std::fs::metadata(dir.join(&rel)).map(|m| manifest::mtime(&m)).unwrap_or(0);
+        manifest.files.insert(rel, FileEntry { size, mtime, hash: None });
+    }
+    manifest
+}
+
+// Hashes the scanned files under dir that size and mtime can't tell apart from the
+// snapshot's: same path, same size. Project files are fixed-size and FAT mtimes are coarse
+// or missing, so an edit may only show in the content. Plain copies get hashed here too.
+pub fn hash_same_size(snap: &mut Snapshot, dir: &Path, scanned: &mut Manifest) -> Result<(), String> {
+    for (rel, entry) in scanned.files.iter_mut() {
+        let Some(theirs) = snap.manifest.files.get_mut(rel).filter(|e| e.size == entry.size) else { continue };
+        if theirs.hash.is_none() && let Some(copy) = &snap.plain {
+            theirs.hash = Some(hash_file(&copy.join(rel))?);
+        }
+        if theirs.hash.is_some() {
+            entry.hash = Some(hash_file(&dir.join(rel))?);
+        }
+    }
+    Ok(())
+}
+
+// Where an object lives in the root, or on a remote: .objects/ab/<hash>
+pub fn object_key(hash: &str) -> Result<String, String> {
+    manifest::check_hash(hash)?;
+    Ok(format!("{}/{}/{}", OBJECTS, &hash[..2], hash))
+}
+
+pub fn object_path(root: &Path, hash: &str) -> Result<PathBuf, String> {
+    Ok(root.join(object_key(hash)?))
+}
+
+pub fn read_object(root: &Path, hash: &str) -> Result<Box<dyn Read>, String> {
+    let key = object_key(hash)?;
+    let sealed_with = crypto::key(root)?;
+    let data = Local::new(root).read(&key)?;
+    match sealed_with {
+        Some(k) => {
+            let plain = crypto::open(&k, hash.as_bytes(), &data).map_err(|e| format!("{}: {}", root.join(&key).display(), e))?;
+            Ok(Box::new(Cursor::new(plain)))
+        }
+        None => Ok(Box::new(Cursor::new(data))),
+    }
+}
+
+// Writes <root>/<name>/manifest.json, or the sealed variant when the root encrypts metadata
+pub fn save_manifest(root: &Path, name: &str, manifest: &Manifest) -> Result<(), String> {
+    let json = manifest.to_json()?;
+    let store = Local::new(root);
+    if !crypto::seals_metadata(root) {
+        return store.write(&format!("{}/{}", name, MANIFEST), &json);
+    }
+    let key = crypto::key(root)?.ok_or("metadata key missing")?;
+    store.write(&format!("{}/{}", name, SEALED_MANIFEST), &crypto::seal(&key, name.as_bytes(), &json)?)
+}
+
+// A manifest as stored under `key` (<name>/manifest.json, or the sealed variant, which
+// is opened with the root's key)
+pub fn decode_manifest(root: &Path, key: &str, bytes: &[u8]) -> Result<Manifest, String> {
+    let Some(name) = key.strip_suffix(&format!("/{}", SEALED_MANIFEST)) else { return Manifest::parse(bytes) };
+    let k = crypto::key(root)?.ok_or("root has no encryption header")?;
+    Manifest::parse(&crypto::open(&k, name.as_bytes(), bytes)?)
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hash_file(path: &Path) -> Result<String, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    hash_reader(file).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn hash_reader(mut reader: impl Read) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher).map_err(|e| e.to_string())?;
+    Ok(hex(&hasher.finalize()))
+}
+
+// Stores src under its hash, sealed in an encrypted root. The (slow) device is read
+// only once: OP-Z files are a few MB at most, so a file is hashed in memory and written
+// from there. Returns the hash and the number of bytes newly stored (0 if already there).
+fn put_object(root: &Path, src: &Path) -> Result<(String, u64), String> {
+    let data = std::fs::read(src).map_err(|e| format!("{}: {}", src.display(), e))?;
+    let hash = hex(&Sha256::digest(&data));
+    let key = object_key(&hash)?;
+    let store = Local::new(root);
+    if store.exists(&key)? {
+        return Ok((hash, 0));
+    }
+    match crypto::key(root)? {
+        Some(k) => store.write(&key, &crypto::seal(&k, hash.as_bytes(), &data)?)?,
+        None => store.write(&key, &data)?,
+    }
+    Ok((hash, data.len() as u64))
+}
+
+// Percentage bar over a known byte total, same shape as backup_copy's
+pub struct Meter {
+    pub bar: Progress,
+    prev: u8,
+    done: u64,
+    total: u64,
+}
+
+impl Meter {
+    pub fn new(total: u64) -> Result<Meter, String> {
+        let bar = progress!(100).map_err(|e| e.to_string())?;
+        Ok(Meter { bar, prev: 0, done: 0, total })
+    }
+
+    pub fn advance(&mut self, bytes: u64, name: &str) {
+        self.done += bytes;
+        let p = pct(self.done, self.total);
+        if p > self.prev {
+            self.bar.inc((p - self.prev) as u64);
+            self.prev = p;
+            self.bar.message(name.to_string());
+        }
+    }
+}
+
+// Stores every file under src and writes <root>/<name>/manifest.json.
+// Returns the number of bytes newly added to the object store.
+pub fn store_snapshot(root: &Path, src: &Path, name: &str) -> Result<u64, String> {
+    store_incremental(root, src, name, &Manifest::default())
+}
+
+// Like store_snapshot, but files whose size and mtime match `base` (the previous
+// snapshot) are not read at all: the new manifest points at base's objects. Over the
+// OP-Z's slow USB link that turns a full copy into a copy of the delta.
+pub fn store_incremental(root: &Path, src: &Path, name: &str, base: &Manifest) -> Result<u64, String> {
+    store_files(root, src, name, scan(src), base)
+}
+
+// A snapshot of just the files under src that match `only` (see path_matches)
+pub fn store_paths(root: &Path, src: &Path, name: &str, only: &[String]) -> Result<u64, String> {
+    let mut device = scan(src);
+    device.files.retain(|rel, _| only.iter().any(|sel| path_matches(rel, sel)));
+    if device.files.is_empty() {
+        return Err(format!("nothing in {} matches {}", src.display(), only.join(", ")));
+    }
+    store_files(root, src, name, device, &Manifest::default())
+}
+
+fn store_files(root: &Path, src: &Path, name: &str, device: Manifest, base: &Manifest) -> Result<u64, String> {
+    let mut manifest = Manifest::new(&src.to_string_lossy());
+    let mut todo = Vec::new();
+
+    for (rel, entry) in device.files {
+        match base.files.get(&rel) {
+            Some(prev) if unchanged(root, prev, &entry) => { manifest.files.insert(rel, prev.clone()); }
+            _ => todo.push((rel, entry)),
+        }
+    }
+    if !base.files.is_empty() {
+        println!("  {} unchanged, {} to copy", manifest.files.len(), todo.len());
+    }
+
+    let mut meter = Meter::new(todo.iter().map(|(_, e)| e.size).sum())?;
+    let mut stored = 0u64;
+    for (rel, entry) in todo {
+        let (hash, bytes) = put_object(root, &src.join(&rel))?;
+        stored += bytes;
+        meter.advance(entry.size, &rel);
+        manifest.files.insert(rel, FileEntry { hash: Some(hash), ..entry });
+    }
+    meter.bar.finish();
+
+    // Manifest goes in last so an interrupted backup never shows up as a snapshot
+    save_manifest(root, name, &manifest)?;
+    Ok(stored)
+}
+
+// Same size and mtime as last time, and the object is still there to point at
+fn unchanged(root: &Path, prev: &FileEntry, now: &FileEntry) -> bool {
+    prev.size == now.size && prev.mtime == now.mtime && now.mtime != 0
+        && prev.hash.as_deref().is_some_and(|h| object_key(h).and_then(|k| Local::new(root).exists(&k)) == Ok(true))
+}
+
+// Writes the snapshot's files into dst, overwriting what is there. Returns bytes written.
+pub fn checkout(root: &Path, snap: &Snapshot, dst: &Path) -> Result<u64, String> {
+    checkout_only(root, snap, dst, |_| true)
+}
+
+// Like checkout, but writes only the files `pick` accepts, e.g. those a restore plan
+// overwrites or adds; the rest of dst isn't touched
+pub fn checkout_only(root: &Path, snap: &Snapshot, dst: &Path, pick: impl Fn(&str) -> bool) -> Result<u64, String> {
+    // Loaded manifests are checked already; this also covers ones built in memory
+    snap.manifest.files.keys().try_for_each(|rel| manifest::check_rel(rel))?;
+    let mut picked = Manifest { files: BTreeMap::new(), ..snap.manifest.clone() };
+    picked.files.extend(snap.manifest.files.iter().filter(|(rel, _)| pick(rel)).map(|(r, e)| (r.clone(), e.clone())));
+    if let Some(path) = &snap.archive {
+        return archive::extract(path, &picked, dst);
+    }
+    let mut meter = Meter::new(picked.total_size())?;
+    let mut bytes = 0u64;
+
+    for (rel, entry) in &picked.files {
+        let mut src = snap.open(root, rel)?;
+        let out = dst.join(rel);
+        if let Some(parent) = out.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut file = std::fs::File::create(&out).map_err(|e| format!("{}: {}", out.display(), e))?;
+        bytes += std::io::copy(&mut src, &mut file).map_err(|e| format!("{}: {}", out.display(), e))?;
+        // Keep the recorded mtime so later size+mtime comparisons see the file as unchanged
+        if entry.mtime > 0 {
+            let time = UNIX_EPOCH + Duration::from_secs(entry.mtime as u64);
+            file.set_modified(time).map_err(|e| format!("{}: {}", out.display(), e))?;
+        }
+        meter.advance(entry.size, rel);
+    }
+    meter.bar.finish();
+    Ok(bytes)
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub missing: Vec<String>,
+    pub extra: Vec<String>,
+    pub corrupted: Vec<String>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+// Re-hashes every file the manifest references and compares it with the recorded hash.
+// Anything in the snapshot folder besides the manifest itself counts as extra.
+pub fn verify(root: &Path, snap: &Snapshot) -> Result<Report, String> {
+    if snap.plain.is_some() {
+        return Err(format!("{} has no manifest to verify against", snap.name));
+    }
+    if let Some(path) = &snap.archive {
+        return archive::verify(path, &snap.manifest);
+    }
+    let mut report = Report::default();
+    let mut meter = Meter::new(snap.manifest.total_size())?;
+    // Unlock up front: a wrong passphrase is an error, not a store full of corrupted objects
+    let sealed = crypto::key(root)?.is_some();
+
+    for (rel, entry) in &snap.manifest.files {
+        let path = snap.content_path(root, rel);
+        match (path, &entry.hash) {
+            (Some(path), Some(hash)) if path.is_file() => match snap.open(root, rel).and_then(hash_reader) {
+                Ok(actual) if actual == *hash => {}
+                Ok(_) => report.corrupted.push(rel.clone()),
+                Err(_) if sealed => report.corrupted.push(rel.clone()),
+                Err(e) => return Err(e),
+            },
+            _ => report.missing.push(rel.clone()),
+        }
+        meter.advance(entry.size, rel);
+    }
+    meter.bar.finish();
+
+    report.extra = walk_dir(&root.join(&snap.name))
+        .into_keys()
+        .filter(|p| p != MANIFEST && p != SEALED_MANIFEST)
+        .collect();
+    Ok(report)
+}
+
+// Re-reads every file the snapshot lists from dir (usually the device) and returns
+// the paths whose content is missing or differs from what the snapshot holds.
+pub fn compare_with(root: &Path, snap: &Snapshot, dir: &Path) -> Result<Vec<String>, String> {
+    let mut meter = Meter::new(snap.manifest.total_size())?;
+    let mut differs = Vec::new();
+
+    for (rel, entry) in &snap.manifest.files {
+        let expected = match &entry.hash {
+            Some(hash) => Some(hash.clone()),
+            None => snap.content_path(root, rel).map(|p| hash_file(&p)).transpose()?,
+        };
+        let actual = hash_file(&dir.join(rel)).ok();
+        if actual.is_none() || actual != expected {
+            differs.push(rel.clone());
+        }
+        meter.advance(entry.size, rel);
+    }
+    meter.bar.finish();
+    Ok(differs)
+}
+
+// Hashes of every object the named snapshots point at (archives carry their own bytes)
+pub fn referenced(root: &Path, names: &[String]) -> Result<HashSet<String>, String> {
+    let mut hashes = HashSet::new();
+    for name in names {
+        let snap = Snapshot::load(root, name)?;
+        if snap.archive.is_some() {
+            continue;
+        }
+        hashes.extend(snap.manifest.files.into_values().filter_map(|f| f.hash));
+    }
+    Ok(hashes)
+}
+
+// Objects no snapshot in `keep` refers to, with their sizes. In-flight temp files are left alone.
+pub fn unreferenced(root: &Path, keep: &HashSet<String>) -> Vec<(PathBuf, u64)> {
+    walk_dir(&root.join(OBJECTS))
+        .into_iter()
+        .filter_map(|(rel, size)| {
+            let hash = Path::new(&rel).file_name()?.to_str()?;
+            (!hash.starts_with('.') && !keep.contains(hash)).then(|| (root.join(OBJECTS).join(&rel), size))
+        })
+        .collect()
+}
+
+// Written when a backup is skipped because the device still matches a snapshot
+pub const LAST_CHECK: &str = ".last-check.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    /// Local time of the check, ISO-8601
+    pub time: String,
+    /// Snapshot the device was found to match
+    pub snapshot: String,
+}
+
+pub fn record_check(root: &Path, snapshot: &str) -> Result<(), String> {
+    let check = Check {
+        time: chrono::Local::now().format("%Y-%m-%dT%H:%M:%S").to_string(),
+        snapshot: snapshot.to_string(),
+    };
+    let text = serde_json::to_string_pretty(&check).map_err(|e| e.to_string())?;
+    std::fs::write(root.join(LAST_CHECK), text).map_err(|e| e.to_string())
+}
+
+pub fn last_check(root: &Path) -> Option<Check> {
+    serde_json::from_str(&std::fs::read_to_string(root.join(LAST_CHECK)).ok()?).ok()
+}
+
+// Bytes actually used by the object store on disk
+pub fn stored_size(root: &Path) -> u64 {
+    walk_dir(&root.join(OBJECTS)).values().sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn device() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("projects")).unwrap();
+        fs::create_dir_all(dir.path().join("samplepacks/1-kick/01")).unwrap();
+        fs::write(dir.path().join("projects/project01.opz"), b"pattern data").unwrap();
+        fs::write(dir.path().join("samplepacks/1-kick/01/kick.aif"), b"kick kick kick").unwrap();
+        dir
+    }
+
+    // ── path_matches / retain_paths / tree_items ────────────────────────────
+
+    #[test]
+    fn path_matches_files_and_folders() {
+        assert!(path_matches("projects/project03.opz", "projects/project03.opz"));
+        assert!(path_matches("samplepacks/1-kick/01/a.aif", "samplepacks/1-kick/"));
+        assert!(path_matches("samplepacks/1-kick/01/a.aif", "samplepacks/1-kick"));
+        assert!(!path_matches("samplepacks/1-kick2/01/a.aif", "samplepacks/1-kick"));
+        assert!(!path_matches("projects/project03.opz", "projects/project0"));
+    }
+
+    #[test]
+    fn retain_paths_keeps_only_selection() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+
+        let mut snap = Snapshot::load(root.path(), "s").unwrap();
+        snap.retain_paths(&["samplepacks/1-kick/".to_string()]).unwrap();
+        assert_eq!(snap.manifest.files.keys().collect::<Vec<_>>(), vec!["samplepacks/1-kick/01/kick.aif"]);
+    }
+
+    #[test]
+    fn retain_paths_errors_on_unmatched_selection() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+
+        let mut snap = Snapshot::load(root.path(), "s").unwrap();
+        assert!(snap.retain_paths(&["projects/project09.opz".to_string()]).is_err());
+    }
+
+    #[test]
+    fn tree_items_lists_folders_then_children() {
+        let snap = scan(device().path());
+        let items = tree_items(&snap.files, 3);
+        let paths: Vec<&str> = items.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(paths, vec![
+            "projects/",
+            "projects/project01.opz",
+            "samplepacks/",
+            "samplepacks/1-kick/",
+            "samplepacks/1-kick/01/",
+        ]);
+        assert_eq!(items[1].1, "  project01.opz");
+        assert_eq!(items[4].1, "    01/");
+    }
+
+    // ── scan ────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn scan_records_sizes_and_mtimes_without_hashes() {
+        let src = device();
+        let m = scan(src.path());
+        assert_eq!(m.source, src.path().to_string_lossy());
+        let entry = &m.files["projects/project01.opz"];
+        assert_eq!((entry.size, entry.hash.as_deref()), (12, None));
+        assert!(entry.mtime > 0);
+    }
+
+    #[test]
+    fn hash_same_size_tells_same_size_edits_apart() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+        let project = src.path().join("projects/project01.opz");
+        let mtime = fs::metadata(&project).unwrap().modified().unwrap();
+        fs::write(&project, b"PATTERN DATA").unwrap();
+        fs::File::options().write(true).open(&project).unwrap().set_modified(mtime).unwrap();
+
+        let mut snap = Snapshot::load(root.path(), "s").unwrap();
+        let mut scanned = scan(src.path());
+        assert!(crate::plan::restore_plan(&scanned, &snap.manifest, false).is_noop());
+        hash_same_size(&mut snap, src.path(), &mut scanned).unwrap();
+        let plan = crate::plan::restore_plan(&scanned, &snap.manifest, false);
+        assert_eq!(plan.writes(), HashSet::from(["projects/project01.opz"]));
+    }
+
+    #[test]
+    fn hash_same_size_hashes_plain_copies_too() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        let copy = root.path().join("old");
+        fs::create_dir_all(copy.join("projects")).unwrap();
+        fs::write(copy.join("projects/project01.opz"), b"pattern DATA").unwrap();
+
+        let mut snap = Snapshot::load(root.path(), "old").unwrap();
+        let mut scanned = scan(src.path());
+        hash_same_size(&mut snap, src.path(), &mut scanned).unwrap();
+        assert!(crate::diff::differs(&scanned.files["projects/project01.opz"], &snap.manifest.files["projects/project01.opz"]));
+    }
+
+    // ── name_time ───────────────────────────────────────────────────────────
+
+    #[test]
+    fn name_time_parses_snapshot_names() {
+        let t = name_time("2026-03-24_14-30-00").unwrap();
+        assert_eq!(t.format("%H:%M").to_string(), "14:30");
+        assert!(name_time("2026-03-24_14-30-00-pre-restore").is_some());
+        assert!(name_time("holiday").is_none());
+    }
+
+    // ── record_check / last_check ──────────────────────────────────────────
+
+    #[test]
+    fn record_check_roundtrips() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(last_check(root.path()), None);
+
+        record_check(root.path(), "2026-03-20_10-00-00").unwrap();
+        let check = last_check(root.path()).unwrap();
+        assert_eq!(check.snapshot, "2026-03-20_10-00-00");
+        assert_eq!(check.time.len(), 19);
+    }
+
+    // ── referenced / unreferenced ───────────────────────────────────────────
+
+    #[test]
+    fn unreferenced_lists_objects_only_dropped_snapshots_use() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "a").unwrap();
+        fs::write(src.path().join("projects/project01.opz"), b"pattern edit").unwrap();
+        store_snapshot(root.path(), src.path(), "b").unwrap();
+
+        let keep = referenced(root.path(), &["b".to_string()]).unwrap();
+        let orphans = unreferenced(root.path(), &keep);
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].1, 12);
+
+        let all = referenced(root.path(), &["a".to_string(), "b".to_string()]).unwrap();
+        assert!(unreferenced(root.path(), &all).is_empty());
+    }
+
+    // ── put_object / object_path ───────────────────────────────────────────
+
+    #[test]
+    fn put_object_names_content_by_sha256_hex() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("f"), b"abc").unwrap();
+
+        let (hash, bytes) = put_object(root.path(), &dir.path().join("f")).unwrap();
+        assert_eq!(hash, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(bytes, 3);
+        assert_eq!(fs::read(object_path(root.path(), &hash).unwrap()).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn hash_file_matches_put_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("f"), b"some sample").unwrap();
+
+        let (hash, _) = put_object(root.path(), &dir.path().join("f")).unwrap();
+        assert_eq!(hash_file(&dir.path().join("f")).unwrap(), hash);
+    }
+
+    #[test]
+    fn object_path_fans_out_on_first_two_chars() {
+        let hash = format!("ab{}", "c".repeat(62));
+        let p = object_path(Path::new("/r"), &hash).unwrap();
+        assert_eq!(p, Path::new("/r/.objects/ab").join(&hash));
+        assert!(object_path(Path::new("/r"), "a").is_err());
+        assert!(object_path(Path::new("/r"), "../../x").is_err());
+    }
+
+    // ── store_snapshot ──────────────────────────────────────────────────────
+
+    #[test]
+    fn store_snapshot_writes_manifest_and_objects() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+
+        let stored = store_snapshot(root.path(), src.path(), "2026-03-20_10-00-00").unwrap();
+        assert_eq!(stored, 26);
+
+        let snap = Snapshot::load(root.path(), "2026-03-20_10-00-00").unwrap();
+        assert!(snap.plain.is_none());
+        assert_eq!(snap.manifest.files.len(), 2);
+        assert_eq!(snap.manifest.source, src.path().to_string_lossy());
+        assert!(snap.manifest.files["projects/project01.opz"].mtime > 0);
+        let obj = snap.content_path(root.path(), "projects/project01.opz").unwrap();
+        assert_eq!(fs::read(obj).unwrap(), b"pattern data");
+    }
+
+    #[test]
+    fn store_paths_keeps_only_selected_files() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+
+        store_paths(root.path(), src.path(), "p", &["projects/project01.opz".to_string()]).unwrap();
+        let snap = Snapshot::load(root.path(), "p").unwrap();
+        assert_eq!(snap.manifest.files.keys().collect::<Vec<_>>(), vec!["projects/project01.opz"]);
+        assert!(store_paths(root.path(), src.path(), "q", &["projects/project02.opz".to_string()]).is_err());
+        assert!(Snapshot::load(root.path(), "q").is_err());
+    }
+
+    #[test]
+    fn store_snapshot_deduplicates_unchanged_content() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+
+        store_snapshot(root.path(), src.path(), "a").unwrap();
+        let again = store_snapshot(root.path(), src.path(), "b").unwrap();
+        assert_eq!(again, 0);
+        assert_eq!(stored_size(root.path()), 26);
+    }
+
+    #[test]
+    fn store_incremental_skips_unchanged_files() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "a").unwrap();
+        let base = Snapshot::load(root.path(), "a").unwrap().manifest;
+
+        // Same size and mtime but different bytes: incremental trusts the metadata
+        let kick = src.path().join("samplepacks/1-kick/01/kick.aif");
+        let mtime = fs::metadata(&kick).unwrap().modified().unwrap();
+        fs::write(&kick, b"KICK KICK KICK").unwrap();
+        fs::File::options().write(true).open(&kick).unwrap().set_modified(mtime).unwrap();
+        fs::write(src.path().join("projects/project02.opz"), b"new project").unwrap();
+
+        let stored = store_incremental(root.path(), src.path(), "b", &base).unwrap();
+        assert_eq!(stored, 11);
+        let snap = Snapshot::load(root.path(), "b").unwrap();
+        assert_eq!(snap.manifest.files.len(), 3);
+        assert_eq!(snap.manifest.files["samplepacks/1-kick/01/kick.aif"], base.files["samplepacks/1-kick/01/kick.aif"]);
+        assert!(verify(root.path(), &snap).unwrap().is_clean());
+    }
+
+    #[test]
+    fn store_incremental_recopies_when_object_missing() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "a").unwrap();
+        let base = Snapshot::load(root.path(), "a").unwrap().manifest;
+        fs::remove_dir_all(root.path().join(OBJECTS)).unwrap();
+
+        assert_eq!(store_incremental(root.path(), src.path(), "b", &base).unwrap(), 26);
+    }
+
+    #[test]
+    fn store_snapshot_shares_identical_files_within_snapshot() {
+        let src = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("a"), b"same").unwrap();
+        fs::write(src.path().join("b"), b"same").unwrap();
+        let root = tempfile::tempdir().unwrap();
+
+        assert_eq!(store_snapshot(root.path(), src.path(), "s").unwrap(), 4);
+    }
+
+    #[test]
+    fn put_object_errors_on_missing_src_file() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(put_object(root.path(), Path::new("/nonexistent/file")).is_err());
+    }
+
+    // ── verify ──────────────────────────────────────────────────────────────
+
+    #[test]
+    fn verify_clean_snapshot_reports_nothing() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+        assert!(verify(root.path(), &snap).unwrap().is_clean());
+    }
+
+    #[test]
+    fn verify_reports_missing_corrupted_and_extra() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+
+        let kick = snap.content_path(root.path(), "samplepacks/1-kick/01/kick.aif").unwrap();
+        let project = snap.content_path(root.path(), "projects/project01.opz").unwrap();
+        fs::remove_file(kick).unwrap();
+        fs::write(project, b"pattern dat4").unwrap();
+        fs::write(root.path().join("s").join("stray.txt"), b"?").unwrap();
+
+        let report = verify(root.path(), &snap).unwrap();
+        assert_eq!(report.missing, vec!["samplepacks/1-kick/01/kick.aif"]);
+        assert_eq!(report.corrupted, vec!["projects/project01.opz"]);
+        assert_eq!(report.extra, vec!["stray.txt"]);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn verify_refuses_plain_copies() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("old")).unwrap();
+        let snap = Snapshot::load(root.path(), "old").unwrap();
+        assert!(verify(root.path(), &snap).is_err());
+    }
+
+    // ── compare_with ────────────────────────────────────────────────────────
+
+    #[test]
+    fn compare_with_matching_tree_is_empty() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+        assert!(compare_with(root.path(), &snap, src.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn compare_with_flags_truncated_and_missing_files() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+        fs::write(src.path().join("projects/project01.opz"), b"pattern").unwrap();
+        fs::remove_file(src.path().join("samplepacks/1-kick/01/kick.aif")).unwrap();
+
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+        assert_eq!(
+            compare_with(root.path(), &snap, src.path()).unwrap(),
+            vec!["projects/project01.opz", "samplepacks/1-kick/01/kick.aif"]
+        );
+    }
+
+    #[test]
+    fn compare_with_hashes_plain_copies() {
+        let root = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("old")).unwrap();
+        fs::write(root.path().join("old/a"), b"same").unwrap();
+        fs::write(dst.path().join("a"), b"diff").unwrap();
+
+        let snap = Snapshot::load(root.path(), "old").unwrap();
+        assert_eq!(compare_with(root.path(), &snap, dst.path()).unwrap(), vec!["a"]);
+    }
+
+    // ── encrypted roots ─────────────────────────────────────────────────────
+
+    #[test]
+    fn encrypted_root_seals_objects_and_reads_them_back() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        crypto::init(root.path(), b"pw", true).unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+        let obj = snap.content_path(root.path(), "projects/project01.opz").unwrap();
+        assert!(!fs::read(&obj).unwrap().windows(7).any(|w| w == b"pattern"));
+        assert!(verify(root.path(), &snap).unwrap().is_clean());
+
+        let dst = tempfile::tempdir().unwrap();
+        checkout(root.path(), &snap, dst.path()).unwrap();
+        assert_eq!(fs::read(dst.path().join("projects/project01.opz")).unwrap(), b"pattern data");
+
+        // Flipping one byte fails authentication, which verify reports as corruption
+        let mut sealed = fs::read(&obj).unwrap();
+        sealed[30] ^= 1;
+        fs::write(&obj, sealed).unwrap();
+        assert_eq!(verify(root.path(), &snap).unwrap().corrupted, vec!["projects/project01.opz"]);
+    }
+
+    #[test]
+    fn encrypted_root_can_seal_manifests() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        crypto::init(root.path(), b"pw", false).unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+
+        assert!(!root.path().join("s").join(MANIFEST).exists());
+        assert!(root.path().join("s").join(SEALED_MANIFEST).is_file());
+        let mut snap = Snapshot::load(root.path(), "s").unwrap();
+        assert!(snap.plain.is_none());
+        assert_eq!(snap.manifest.files.len(), 2);
+
+        snap.manifest.failed = true;
+        snap.save_manifest(root.path()).unwrap();
+        assert!(Snapshot::load(root.path(), "s").unwrap().manifest.failed);
+        assert!(verify(root.path(), &snap).unwrap().extra.is_empty());
+    }
+
+    // ── Snapshot::load / checkout ───────────────────────────────────────────
+
+    #[test]
+    fn load_treats_dir_without_manifest_as_plain_copy() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("old");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("x.txt"), b"12345").unwrap();
+
+        let snap = Snapshot::load(root.path(), "old").unwrap();
+        assert_eq!(snap.plain.as_deref(), Some(dir.as_path()));
+        let entry = &snap.manifest.files["x.txt"];
+        assert_eq!((entry.size, entry.hash.as_deref()), (5, None));
+        assert!(entry.mtime > 0);
+        assert_eq!(snap.content_path(root.path(), "x.txt"), Some(dir.join("x.txt")));
+    }
+
+    #[test]
+    fn load_errors_on_unknown_name() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(Snapshot::load(root.path(), "nope").is_err());
+    }
+
+    #[test]
+    fn checkout_recreates_tree() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+        assert_eq!(checkout(root.path(), &snap, dst.path()).unwrap(), 26);
+        assert_eq!(walk_dir(dst.path()), walk_dir(src.path()));
+        assert_eq!(fs::read(dst.path().join("samplepacks/1-kick/01/kick.aif")).unwrap(), b"kick kick kick");
+        assert_eq!(scan(dst.path()).files, scan(src.path()).files);
+    }
+
+    #[test]
+    fn checkout_only_leaves_other_files_alone() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+        fs::create_dir_all(dst.path().join("projects")).unwrap();
+        fs::write(dst.path().join("projects/project01.opz"), b"on the device").unwrap();
+
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+        assert_eq!(checkout_only(root.path(), &snap, dst.path(), |rel| rel.starts_with("samplepacks/")).unwrap(), 14);
+        assert_eq!(fs::read(dst.path().join("projects/project01.opz")).unwrap(), b"on the device");
+        assert_eq!(fs::read(dst.path().join("samplepacks/1-kick/01/kick.aif")).unwrap(), b"kick kick kick");
+    }
+
+    #[test]
+    fn checkout_errors_when_object_missing() {
+        let src = device();
+        let root = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        store_snapshot(root.path(), src.path(), "s").unwrap();
+        fs::remove_dir_all(root.path().join(OBJECTS)).unwrap();
+
+        let snap = Snapshot::load(root.path(), "s").unwrap();
+        assert!(checkout(root.path(), &snap, dst.path()).is_err());
+    }
+}